@@ -1,4 +1,4 @@
-use draft_together_data::Draft;
+use draft_together_data::ServerMessage;
 use futures_util::{SinkExt, StreamExt};
 use std::io::BufRead;
 use tokio::runtime::Handle;
@@ -58,14 +58,21 @@ async fn spawn_client(who: usize) {
             match msg {
                 Message::Close(_) => break,
                 Message::Text(msg) => {
-                    let draft: Draft = match serde_json::from_str(&msg) {
-                        Ok(draft) => draft,
+                    let message: ServerMessage = match serde_json::from_str(&msg) {
+                        Ok(message) => message,
                         Err(e) => {
-                            println!("failed to deserialize message as draft: {e}");
+                            println!("failed to deserialize server message: {e}");
                             break;
                         }
                     };
-                    println!("current draft state: {}", draft.display());
+                    match message {
+                        ServerMessage::DraftState { draft } => {
+                            println!("current draft state: {}", draft.display())
+                        }
+                        ServerMessage::Error { reason } => println!("server error: {reason}"),
+                        ServerMessage::Ack => {}
+                        message => println!("server message: {message:?}"),
+                    }
                 }
                 _ => {}
             }
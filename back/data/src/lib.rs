@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Champion {
@@ -10,7 +11,7 @@ pub struct Champion {
     pub positions: Vec<ChampionRole>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChampionRole {
     TOP,
     JUNGLE,
@@ -19,7 +20,17 @@ pub enum ChampionRole {
     SUPPORT,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+impl ChampionRole {
+    pub const ALL: [ChampionRole; 5] = [
+        ChampionRole::TOP,
+        ChampionRole::JUNGLE,
+        ChampionRole::MID,
+        ChampionRole::BOT,
+        ChampionRole::SUPPORT,
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChampionPosition {
     Blue1,
     Blue2,
@@ -43,20 +54,484 @@ pub enum ChampionPosition {
     RedBan5,
 }
 
+impl ChampionPosition {
+    pub const ALL: [ChampionPosition; 20] = [
+        ChampionPosition::Blue1,
+        ChampionPosition::Blue2,
+        ChampionPosition::Blue3,
+        ChampionPosition::Blue4,
+        ChampionPosition::Blue5,
+        ChampionPosition::Red1,
+        ChampionPosition::Red2,
+        ChampionPosition::Red3,
+        ChampionPosition::Red4,
+        ChampionPosition::Red5,
+        ChampionPosition::BlueBan1,
+        ChampionPosition::BlueBan2,
+        ChampionPosition::BlueBan3,
+        ChampionPosition::BlueBan4,
+        ChampionPosition::BlueBan5,
+        ChampionPosition::RedBan1,
+        ChampionPosition::RedBan2,
+        ChampionPosition::RedBan3,
+        ChampionPosition::RedBan4,
+        ChampionPosition::RedBan5,
+    ];
+
+    pub fn team(&self) -> Team {
+        match self {
+            ChampionPosition::Blue1
+            | ChampionPosition::Blue2
+            | ChampionPosition::Blue3
+            | ChampionPosition::Blue4
+            | ChampionPosition::Blue5
+            | ChampionPosition::BlueBan1
+            | ChampionPosition::BlueBan2
+            | ChampionPosition::BlueBan3
+            | ChampionPosition::BlueBan4
+            | ChampionPosition::BlueBan5 => Team::Blue,
+            _ => Team::Red,
+        }
+    }
+
+    pub fn action(&self) -> DraftAction {
+        match self {
+            ChampionPosition::BlueBan1
+            | ChampionPosition::BlueBan2
+            | ChampionPosition::BlueBan3
+            | ChampionPosition::BlueBan4
+            | ChampionPosition::BlueBan5
+            | ChampionPosition::RedBan1
+            | ChampionPosition::RedBan2
+            | ChampionPosition::RedBan3
+            | ChampionPosition::RedBan4
+            | ChampionPosition::RedBan5 => DraftAction::Ban,
+            _ => DraftAction::Pick,
+        }
+    }
+
+    /// Index of the position among the positions of the same team and action, from 0 to 4.
+    pub fn index(&self) -> usize {
+        let position = ChampionPosition::ALL
+            .iter()
+            .position(|other| other == self)
+            .expect("every position is in ChampionPosition::ALL");
+        position % MAX_SIDE_SLOTS
+    }
+
+    /// The position of `team` for `action` at `index`, `None` if `index` is out of range.
+    pub fn new(team: Team, action: DraftAction, index: usize) -> Option<Self> {
+        ChampionPosition::ALL.into_iter().find(|position| {
+            position.team() == team && position.action() == action && position.index() == index
+        })
+    }
+}
+
+/// Number of picks, and of bans, a team can have at most.
+pub const MAX_SIDE_SLOTS: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DraftAction {
+    Ban,
+    Pick,
+}
+
+/// Consecutive tournament turns of the same action.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DraftPhase {
+    pub action: DraftAction,
+    /// Team playing each turn of the phase, each turn fills the next free position of the team.
+    pub teams: Vec<Team>,
+}
+
+/// Positions used by a draft, and the order they are filled in tournament mode.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DraftFormat {
+    pub picks_per_side: usize,
+    pub bans_per_side: usize,
+    pub phases: Vec<DraftPhase>,
+}
+
+impl Default for DraftFormat {
+    /// Standard competitive format: ban phase 1, pick phase 1, ban phase 2 and pick phase 2.
+    fn default() -> Self {
+        use DraftAction::{Ban, Pick};
+        use Team::{Blue, Red};
+
+        let phase = |action, teams: &[Team]| DraftPhase {
+            action,
+            teams: teams.to_vec(),
+        };
+        Self {
+            picks_per_side: 5,
+            bans_per_side: 5,
+            phases: vec![
+                phase(Ban, &[Blue, Red, Blue, Red, Blue, Red]),
+                phase(Pick, &[Blue, Red, Red, Blue, Blue, Red]),
+                phase(Ban, &[Red, Blue, Red, Blue]),
+                phase(Pick, &[Red, Blue, Blue, Red]),
+            ],
+        }
+    }
+}
+
+impl DraftFormat {
+    fn slots_per_side(&self, action: DraftAction) -> usize {
+        match action {
+            DraftAction::Pick => self.picks_per_side,
+            DraftAction::Ban => self.bans_per_side,
+        }
+    }
+
+    /// Whether `position` is used by drafts of this format.
+    pub fn contains(&self, position: ChampionPosition) -> bool {
+        position.index() < self.slots_per_side(position.action())
+    }
+
+    /// Positions in the order they are filled in tournament mode.
+    pub fn order(&self) -> Vec<ChampionPosition> {
+        let mut filled = [[0; 2]; 2];
+        self.phases
+            .iter()
+            .flat_map(|phase| phase.teams.iter().map(|&team| (team, phase.action)))
+            .filter_map(|(team, action)| {
+                let count = &mut filled[team as usize][action as usize];
+                *count += 1;
+                ChampionPosition::new(team, action, *count - 1)
+            })
+            .collect()
+    }
+
+    /// The turn at `step` of the tournament order, `None` once every turn is played.
+    pub fn turn(&self, step: usize) -> Option<Turn> {
+        self.order().get(step).map(|&position| Turn {
+            step,
+            team: position.team(),
+            action: position.action(),
+            position,
+        })
+    }
+
+    /// Checks that each team has at most `MAX_SIDE_SLOTS` picks and bans, and that the phases
+    /// fill every position of the format exactly once.
+    pub fn validate(&self) -> Result<(), DraftError> {
+        if self.picks_per_side > MAX_SIDE_SLOTS || self.bans_per_side > MAX_SIDE_SLOTS {
+            return Err(DraftError::InvalidFormat(format!(
+                "a team can not have more than {MAX_SIDE_SLOTS} picks or bans"
+            )));
+        }
+        for team in [Team::Blue, Team::Red] {
+            for action in [DraftAction::Pick, DraftAction::Ban] {
+                let turns = self
+                    .phases
+                    .iter()
+                    .filter(|phase| phase.action == action)
+                    .flat_map(|phase| &phase.teams)
+                    .filter(|&&phase_team| phase_team == team)
+                    .count();
+                let slots = self.slots_per_side(action);
+                if turns != slots {
+                    return Err(DraftError::InvalidFormat(format!(
+                        "{team:?} has {turns} {action:?} turns for {slots} positions"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The action due in a tournament draft.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Turn {
+    /// Index of the action in the order of the draft format.
+    pub step: usize,
+    pub team: Team,
+    pub action: DraftAction,
+    pub position: ChampionPosition,
+}
+
 pub type ChampionId = i32;
 
+pub type SessionId = Uuid;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Team {
+    Blue,
+    Red,
+}
+
+/// A client connected to a draft.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: SessionId,
+    pub name: Option<String>,
+    pub team: Option<Team>,
+    /// Champion the client is currently considering, never applied to the draft.
+    pub hover: Option<ChampionUpdate>,
+    /// Joined with the read-only link of the draft.
+    pub spectator: bool,
+}
+
+/// Incremented by the server every time a draft is modified.
+pub type Revision = u64;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChampionUpdate {
     pub champion_id: Option<ChampionId>,
     pub position: ChampionPosition,
 }
 
+/// Slots changed by the edit that brought a draft to `revision`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftChanges {
+    pub changes: Vec<ChampionUpdate>,
+    pub revision: Revision,
+}
+
+/// Operation applied to a draft, as recorded in its event log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DraftOperation {
+    /// Slots changed by an edit, an undo, a redo, a trade or a turn timeout, and the tournament
+    /// step after it.
+    Update {
+        changes: Vec<ChampionUpdate>,
+        #[serde(default)]
+        step: usize,
+    },
+    /// A timed out tournament turn left empty.
+    SkipTurn {
+        step: usize,
+    },
+    Roles {
+        team: Team,
+        roles: [Option<ChampionRole>; 5],
+    },
+    Settings {
+        settings: DraftSettings,
+        step: usize,
+    },
+    LockIn {
+        team: Team,
+    },
+    /// Roles, lock-ins and turn cleared by a reset, its slots are cleared by the `Update` of the
+    /// same revision.
+    Reset,
+}
+
+/// Entry of the append-only event log of a draft.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftEvent {
+    /// Position of the event in the log of its draft, from 1. Several events can leave the draft
+    /// at the same revision, e.g. roles or settings changes, the sequence orders them.
+    pub sequence: u64,
+    /// Revision of the draft once the operation is applied.
+    pub revision: Revision,
+    /// Session that made the operation, `None` if the server did, e.g. on a turn timeout.
+    pub session_id: Option<SessionId>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub operation: DraftOperation,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DraftMode {
+    /// Any position can be edited at any time.
+    #[default]
+    Free,
+    /// Positions are filled one at a time, following the phases of the draft format.
+    Tournament,
+    /// Each team fills its own picks without seeing the picks of the other team, which are
+    /// revealed once both teams locked in. Both teams can pick the same champion.
+    Blind,
+}
+
+/// What the server does when a tournament turn runs out of time.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TimeoutFallback {
+    /// The position stays empty and the draft moves to the next turn.
+    #[default]
+    Skip,
+    /// The champion hovered for the position by the team whose turn it is is locked, the turn is
+    /// skipped if there is none.
+    LockHover,
+    /// A random champion that is not in the draft yet is locked.
+    Random,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimerSettings {
+    pub turn_seconds: u64,
+    /// Time each team can spend once the turn time is over, for the whole draft.
+    pub reserve_seconds: u64,
+    pub fallback: TimeoutFallback,
+}
+
+impl Default for TimerSettings {
+    fn default() -> Self {
+        Self {
+            turn_seconds: 30,
+            reserve_seconds: 0,
+            fallback: TimeoutFallback::default(),
+        }
+    }
+}
+
+/// Remaining time of the current tournament turn.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerState {
+    pub team: Team,
+    pub remaining_seconds: u64,
+    pub blue_reserve_seconds: u64,
+    pub red_reserve_seconds: u64,
+}
+
+/// Stage of a draft, from the lobby where teams get ready to its final result.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DraftLifecycle {
+    /// Teams are getting ready, only free drafts can already be edited.
+    #[default]
+    Lobby,
+    /// Both teams are ready, the draft starts with its first action.
+    Ready,
+    InProgress,
+    /// Every action of the draft is done, picks can still be traded or edited.
+    Completed,
+    /// The result is final, the draft can no longer be edited.
+    Locked,
+}
+
+/// Options chosen for a draft, kept when the draft is reset.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DraftSettings {
+    /// Lets a champion be picked or banned several times, for theorycrafting.
+    pub allow_duplicate_champions: bool,
+    pub mode: DraftMode,
+    /// Turn timer of tournament drafts, turns are not timed when `None`.
+    pub timer: Option<TimerSettings>,
+    pub format: DraftFormat,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DraftError {
+    #[error("champion {champion_id} is already in {position:?}")]
+    DuplicateChampion {
+        champion_id: ChampionId,
+        position: ChampionPosition,
+    },
+    #[error("{position:?} is not due, next position is {expected:?}")]
+    NotYourTurn {
+        position: ChampionPosition,
+        expected: ChampionPosition,
+    },
+    #[error("every position of the draft is already filled")]
+    DraftComplete,
+    #[error("a champion has to be chosen")]
+    EmptyTurn,
+    #[error("this edit is only allowed in free mode")]
+    NotFreeMode,
+    #[error("champion {champion_id} was already picked in a previous game of the series")]
+    ChampionLocked { champion_id: ChampionId },
+    #[error("{position:?} is not used by the draft format")]
+    PositionNotInFormat { position: ChampionPosition },
+    #[error("invalid draft format: {0}")]
+    InvalidFormat(String),
+    #[error("{position:?} belongs to the other team")]
+    NotYourSide { position: ChampionPosition },
+    #[error("{team:?} already locked in its picks")]
+    TeamLockedIn { team: Team },
+    #[error("{team:?} has to fill all of its picks before locking in")]
+    IncompletePicks { team: Team },
+    #[error("locking in is only allowed in blind mode")]
+    NotBlindMode,
+    #[error("{position:?} is not a pick")]
+    NotAPick { position: ChampionPosition },
+    #[error("picks can only be traded once every champion is picked")]
+    NotTradePhase,
+    #[error("the draft starts once both teams are ready")]
+    NotStarted,
+    #[error("the draft already started")]
+    AlreadyStarted,
+    #[error("the draft is locked")]
+    DraftLocked,
+    #[error("{from:?} and {to:?} do not belong to the same team")]
+    DifferentTeams {
+        from: ChampionPosition,
+        to: ChampionPosition,
+    },
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Draft {
     pub blue_champions: [Option<ChampionId>; 5],
     pub red_champions: [Option<ChampionId>; 5],
     pub blue_bans: [Option<ChampionId>; 5],
     pub red_bans: [Option<ChampionId>; 5],
+    #[serde(default)]
+    pub revision: Revision,
+    #[serde(default)]
+    pub settings: DraftSettings,
+    /// Number of actions done in a tournament draft.
+    #[serde(default)]
+    pub step: usize,
+    /// Champions picked in the previous games of the series, they can not be picked again.
+    #[serde(default)]
+    pub locked_champions: Vec<ChampionId>,
+    /// Teams whose picks are final in a blind draft.
+    #[serde(default)]
+    pub locked_in: Vec<Team>,
+    /// Role played by each pick of the team, in the same order as `blue_champions`.
+    #[serde(default)]
+    pub blue_roles: [Option<ChampionRole>; 5],
+    #[serde(default)]
+    pub red_roles: [Option<ChampionRole>; 5],
+}
+
+/// Secret ids giving a restricted access to a draft, issued when it is created. Each of them is
+/// used in place of the draft client id, which must not be shared since it allows to edit both
+/// teams.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftLinks {
+    /// Joins the draft without being able to edit it.
+    pub spectator_id: Uuid,
+    /// Joins the draft only allowed to edit the positions of the blue team.
+    pub blue_token: Uuid,
+    /// Joins the draft only allowed to edit the positions of the red team.
+    pub red_token: Uuid,
+}
+
+impl DraftLinks {
+    /// The team whose captain holds `token`, if any.
+    pub fn captain_team(&self, token: Uuid) -> Option<Team> {
+        if token == self.blue_token {
+            Some(Team::Blue)
+        } else if token == self.red_token {
+            Some(Team::Red)
+        } else {
+            None
+        }
+    }
+}
+
+/// Exchange of two picks of the same team, waiting to be accepted by another session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeProposal {
+    pub id: Uuid,
+    pub from: ChampionPosition,
+    pub to: ChampionPosition,
+    pub proposed_by: SessionId,
+}
+
+/// Several drafts played one after the other, where a picked champion can not be picked again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Series {
+    /// Client ids of the drafts, in game order.
+    pub games: Vec<Uuid>,
+    /// Champions picked in any game of the series.
+    pub locked_champions: Vec<ChampionId>,
 }
 
 impl Draft {
@@ -71,28 +546,837 @@ impl Draft {
         .to_string()
     }
 
+    /// Champions picked by both teams.
+    pub fn picks(&self) -> impl Iterator<Item = ChampionId> + '_ {
+        self.blue_champions
+            .iter()
+            .chain(&self.red_champions)
+            .filter_map(|&champion_id| champion_id)
+    }
+
+    pub fn get(&self, position: ChampionPosition) -> Option<ChampionId> {
+        match position {
+            ChampionPosition::Blue1 => self.blue_champions[0],
+            ChampionPosition::Blue2 => self.blue_champions[1],
+            ChampionPosition::Blue3 => self.blue_champions[2],
+            ChampionPosition::Blue4 => self.blue_champions[3],
+            ChampionPosition::Blue5 => self.blue_champions[4],
+            ChampionPosition::Red1 => self.red_champions[0],
+            ChampionPosition::Red2 => self.red_champions[1],
+            ChampionPosition::Red3 => self.red_champions[2],
+            ChampionPosition::Red4 => self.red_champions[3],
+            ChampionPosition::Red5 => self.red_champions[4],
+            ChampionPosition::BlueBan1 => self.blue_bans[0],
+            ChampionPosition::BlueBan2 => self.blue_bans[1],
+            ChampionPosition::BlueBan3 => self.blue_bans[2],
+            ChampionPosition::BlueBan4 => self.blue_bans[3],
+            ChampionPosition::BlueBan5 => self.blue_bans[4],
+            ChampionPosition::RedBan1 => self.red_bans[0],
+            ChampionPosition::RedBan2 => self.red_bans[1],
+            ChampionPosition::RedBan3 => self.red_bans[2],
+            ChampionPosition::RedBan4 => self.red_bans[3],
+            ChampionPosition::RedBan5 => self.red_bans[4],
+        }
+    }
+
+    fn slot_mut(&mut self, position: ChampionPosition) -> &mut Option<ChampionId> {
+        match position {
+            ChampionPosition::Blue1 => &mut self.blue_champions[0],
+            ChampionPosition::Blue2 => &mut self.blue_champions[1],
+            ChampionPosition::Blue3 => &mut self.blue_champions[2],
+            ChampionPosition::Blue4 => &mut self.blue_champions[3],
+            ChampionPosition::Blue5 => &mut self.blue_champions[4],
+            ChampionPosition::Red1 => &mut self.red_champions[0],
+            ChampionPosition::Red2 => &mut self.red_champions[1],
+            ChampionPosition::Red3 => &mut self.red_champions[2],
+            ChampionPosition::Red4 => &mut self.red_champions[3],
+            ChampionPosition::Red5 => &mut self.red_champions[4],
+            ChampionPosition::BlueBan1 => &mut self.blue_bans[0],
+            ChampionPosition::BlueBan2 => &mut self.blue_bans[1],
+            ChampionPosition::BlueBan3 => &mut self.blue_bans[2],
+            ChampionPosition::BlueBan4 => &mut self.blue_bans[3],
+            ChampionPosition::BlueBan5 => &mut self.blue_bans[4],
+            ChampionPosition::RedBan1 => &mut self.red_bans[0],
+            ChampionPosition::RedBan2 => &mut self.red_bans[1],
+            ChampionPosition::RedBan3 => &mut self.red_bans[2],
+            ChampionPosition::RedBan4 => &mut self.red_bans[3],
+            ChampionPosition::RedBan5 => &mut self.red_bans[4],
+        }
+    }
+
     pub fn update(&mut self, champion_update: &ChampionUpdate) {
-        match champion_update.position {
-            ChampionPosition::Blue1 => self.blue_champions[0] = champion_update.champion_id,
-            ChampionPosition::Blue2 => self.blue_champions[1] = champion_update.champion_id,
-            ChampionPosition::Blue3 => self.blue_champions[2] = champion_update.champion_id,
-            ChampionPosition::Blue4 => self.blue_champions[3] = champion_update.champion_id,
-            ChampionPosition::Blue5 => self.blue_champions[4] = champion_update.champion_id,
-            ChampionPosition::Red1 => self.red_champions[0] = champion_update.champion_id,
-            ChampionPosition::Red2 => self.red_champions[1] = champion_update.champion_id,
-            ChampionPosition::Red3 => self.red_champions[2] = champion_update.champion_id,
-            ChampionPosition::Red4 => self.red_champions[3] = champion_update.champion_id,
-            ChampionPosition::Red5 => self.red_champions[4] = champion_update.champion_id,
-            ChampionPosition::BlueBan1 => self.blue_bans[0] = champion_update.champion_id,
-            ChampionPosition::BlueBan2 => self.blue_bans[1] = champion_update.champion_id,
-            ChampionPosition::BlueBan3 => self.blue_bans[2] = champion_update.champion_id,
-            ChampionPosition::BlueBan4 => self.blue_bans[3] = champion_update.champion_id,
-            ChampionPosition::BlueBan5 => self.blue_bans[4] = champion_update.champion_id,
-            ChampionPosition::RedBan1 => self.red_bans[0] = champion_update.champion_id,
-            ChampionPosition::RedBan2 => self.red_bans[1] = champion_update.champion_id,
-            ChampionPosition::RedBan3 => self.red_bans[2] = champion_update.champion_id,
-            ChampionPosition::RedBan4 => self.red_bans[3] = champion_update.champion_id,
-            ChampionPosition::RedBan5 => self.red_bans[4] = champion_update.champion_id,
+        *self.slot_mut(champion_update.position) = champion_update.champion_id;
+    }
+
+    /// Same as `update`, but the draft is left untouched if the update breaks `validate_position`.
+    /// In tournament mode, only the due position can be filled and the draft moves to the next
+    /// turn.
+    pub fn try_update(&mut self, champion_update: &ChampionUpdate) -> Result<(), DraftError> {
+        let tournament = self.settings.mode == DraftMode::Tournament;
+        if tournament {
+            let turn = self.next_turn().ok_or(DraftError::DraftComplete)?;
+            if champion_update.position != turn.position {
+                return Err(DraftError::NotYourTurn {
+                    position: champion_update.position,
+                    expected: turn.position,
+                });
+            }
+            if champion_update.champion_id.is_none() {
+                return Err(DraftError::EmptyTurn);
+            }
+        }
+
+        let previous = self.get(champion_update.position);
+        self.update(champion_update);
+        self.validate_position(champion_update.position)
+            .inspect_err(|_| *self.slot_mut(champion_update.position) = previous)?;
+        if tournament {
+            self.step += 1;
+        }
+        Ok(())
+    }
+
+    /// The action due in a tournament draft, `None` in free mode or once every action is done.
+    pub fn next_turn(&self) -> Option<Turn> {
+        if self.settings.mode != DraftMode::Tournament {
+            return None;
+        }
+        self.settings.format.turn(self.step)
+    }
+
+    /// Moves a tournament draft to the next turn, leaving the due position empty.
+    pub fn skip_turn(&mut self) {
+        if self.next_turn().is_some() {
+            self.step += 1;
+        }
+    }
+
+    /// Errors if the draft does not allow edits outside of the turn order.
+    pub fn ensure_free_mode(&self) -> Result<(), DraftError> {
+        match self.settings.mode {
+            DraftMode::Free => Ok(()),
+            DraftMode::Tournament | DraftMode::Blind => Err(DraftError::NotFreeMode),
+        }
+    }
+
+    /// Errors if a client of `team` can not edit `position`. In blind mode, picks can only be
+    /// edited by their own team, until it locks in.
+    pub fn ensure_editable_by(
+        &self,
+        position: ChampionPosition,
+        team: Option<Team>,
+    ) -> Result<(), DraftError> {
+        if self.settings.mode != DraftMode::Blind || position.action() != DraftAction::Pick {
+            return Ok(());
+        }
+        if team != Some(position.team()) {
+            return Err(DraftError::NotYourSide { position });
+        }
+        if self.locked_in.contains(&position.team()) {
+            return Err(DraftError::TeamLockedIn {
+                team: position.team(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Makes the picks of `team` final in a blind draft, they are revealed once both teams
+    /// locked in.
+    pub fn lock_in(&mut self, team: Team) -> Result<(), DraftError> {
+        if self.settings.mode != DraftMode::Blind {
+            return Err(DraftError::NotBlindMode);
+        }
+        if self.locked_in.contains(&team) {
+            return Err(DraftError::TeamLockedIn { team });
+        }
+        let complete = ChampionPosition::ALL.into_iter().all(|position| {
+            position.team() != team
+                || position.action() != DraftAction::Pick
+                || !self.settings.format.contains(position)
+                || self.get(position).is_some()
+        });
+        if !complete {
+            return Err(DraftError::IncompletePicks { team });
+        }
+        self.locked_in.push(team);
+        Ok(())
+    }
+
+    /// Whether every action of the draft is done: every turn of a tournament draft is played,
+    /// or every position of the format is filled otherwise.
+    pub fn is_complete(&self) -> bool {
+        match self.settings.mode {
+            DraftMode::Tournament => self.next_turn().is_none(),
+            DraftMode::Free | DraftMode::Blind => {
+                ChampionPosition::ALL.into_iter().all(|position| {
+                    !self.settings.format.contains(position) || self.get(position).is_some()
+                })
+            }
+        }
+    }
+
+    /// Whether the draft is in its trade phase, where teammates exchange their picks. Only
+    /// tournament and blind drafts have one, starting after the last pick of the draft.
+    pub fn trade_phase(&self) -> bool {
+        let picks_done = ChampionPosition::ALL.into_iter().all(|position| {
+            position.action() != DraftAction::Pick
+                || !self.settings.format.contains(position)
+                || self.get(position).is_some()
+        });
+        picks_done
+            && match self.settings.mode {
+                DraftMode::Free => false,
+                DraftMode::Tournament => self.next_turn().is_none(),
+                DraftMode::Blind => self.revealed(),
+            }
+    }
+
+    /// Errors if the picks at `from` and `to` can not be traded.
+    pub fn ensure_tradable(
+        &self,
+        from: ChampionPosition,
+        to: ChampionPosition,
+    ) -> Result<(), DraftError> {
+        if !self.trade_phase() {
+            return Err(DraftError::NotTradePhase);
+        }
+        if let Some(position) = [from, to]
+            .into_iter()
+            .find(|position| position.action() != DraftAction::Pick)
+        {
+            return Err(DraftError::NotAPick { position });
+        }
+        if from.team() != to.team() {
+            return Err(DraftError::DifferentTeams { from, to });
+        }
+        Ok(())
+    }
+
+    /// Whether every client can see all the picks, only false in blind drafts before both teams
+    /// locked in.
+    pub fn revealed(&self) -> bool {
+        self.settings.mode != DraftMode::Blind
+            || [Team::Blue, Team::Red]
+                .iter()
+                .all(|team| self.locked_in.contains(team))
+    }
+
+    /// Whether the champion at `position` is hidden from the clients of `team`.
+    pub fn is_hidden_from(&self, position: ChampionPosition, team: Option<Team>) -> bool {
+        position.action() == DraftAction::Pick && team != Some(position.team()) && !self.revealed()
+    }
+
+    /// The draft as seen by the clients of `team`, without the picks hidden from them.
+    pub fn view_for(&self, team: Option<Team>) -> Draft {
+        self.view_hidden_like(self, team)
+    }
+
+    /// Same as `view_for`, hiding the picks hidden from `team` in `current` instead, e.g. when
+    /// `self` is an older state of `current`.
+    pub fn view_hidden_like(&self, current: &Draft, team: Option<Team>) -> Draft {
+        let mut draft = self.clone();
+        for position in ChampionPosition::ALL {
+            if current.is_hidden_from(position, team) {
+                *draft.slot_mut(position) = None;
+                draft.roles_mut(position.team())[position.index()] = None;
+            }
+        }
+        draft
+    }
+
+    /// `changes` without the slots hidden from the clients of `team`.
+    pub fn visible_changes(&self, changes: &DraftChanges, team: Option<Team>) -> DraftChanges {
+        DraftChanges {
+            changes: changes
+                .changes
+                .iter()
+                .filter(|change| !self.is_hidden_from(change.position, team))
+                .cloned()
+                .collect(),
+            revision: changes.revision,
+        }
+    }
+
+    /// Changes the settings of the draft. Entering tournament mode, or changing the format of a
+    /// tournament draft, resumes the turn order after the positions that are already filled.
+    /// Fails, keeping the previous settings, if the new format is invalid or if the board is not
+    /// valid with the new settings, e.g. duplicates that are no longer allowed.
+    pub fn set_settings(&mut self, settings: DraftSettings) -> Result<(), DraftError> {
+        settings.format.validate()?;
+        let previous = std::mem::replace(&mut self.settings, settings);
+        if let Err(e) = self.validate() {
+            self.settings = previous;
+            return Err(e);
+        }
+
+        let resume_order = self.settings.mode == DraftMode::Tournament
+            && (previous.mode != DraftMode::Tournament || previous.format != self.settings.format);
+        if resume_order {
+            self.step = self
+                .settings
+                .format
+                .order()
+                .into_iter()
+                .take_while(|&position| self.get(position).is_some())
+                .count();
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the draft as it was once the event `sequence` of its event log was applied,
+    /// oldest event first. Operations are applied as recorded, without validating them.
+    pub fn replay(events: &[DraftEvent], sequence: u64) -> Self {
+        let mut draft = Draft::default();
+        for event in events.iter().take_while(|event| event.sequence <= sequence) {
+            match &event.operation {
+                DraftOperation::Update { changes, step } => {
+                    for change in changes {
+                        draft.update(change);
+                    }
+                    draft.step = *step;
+                }
+                DraftOperation::SkipTurn { step } => draft.step = *step,
+                DraftOperation::Roles { team, roles } => *draft.roles_mut(*team) = *roles,
+                DraftOperation::Settings { settings, step } => {
+                    draft.settings = settings.clone();
+                    draft.step = *step;
+                }
+                DraftOperation::LockIn { team } => {
+                    if !draft.locked_in.contains(team) {
+                        draft.locked_in.push(*team);
+                    }
+                }
+                DraftOperation::Reset => draft.reset(),
+            }
+            draft.revision = event.revision;
+        }
+        draft
+    }
+
+    /// Checks that no champion is picked or banned twice, unless the draft settings allow it.
+    pub fn validate(&self) -> Result<(), DraftError> {
+        ChampionPosition::ALL
+            .into_iter()
+            .try_for_each(|position| self.validate_position(position))
+    }
+
+    /// Checks that a filled `position` is used by the draft format, that its champion is not
+    /// locked by the series if it is picked, and that it is not also at another position unless
+    /// the draft settings allow it.
+    pub fn validate_position(&self, position: ChampionPosition) -> Result<(), DraftError> {
+        let Some(champion_id) = self.get(position) else {
+            return Ok(());
+        };
+        if !self.settings.format.contains(position) {
+            return Err(DraftError::PositionNotInFormat { position });
+        }
+        if position.action() == DraftAction::Pick && self.locked_champions.contains(&champion_id) {
+            return Err(DraftError::ChampionLocked { champion_id });
+        }
+        if self.settings.allow_duplicate_champions {
+            return Ok(());
+        }
+
+        // in blind mode the picks of the other team are hidden, both teams can pick a champion
+        let blind = self.settings.mode == DraftMode::Blind;
+        match ChampionPosition::ALL.into_iter().find(|&other| {
+            other != position
+                && self.get(other) == Some(champion_id)
+                && !(blind
+                    && other.action() == DraftAction::Pick
+                    && other.team() != position.team())
+        }) {
+            Some(other) => Err(DraftError::DuplicateChampion {
+                champion_id,
+                position: other,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Exchanges the champions of both positions, either of them can be empty.
+    pub fn swap(&mut self, from: ChampionPosition, to: ChampionPosition) {
+        let from_champion = self.get(from);
+        let to_champion = self.get(to);
+        *self.slot_mut(to) = from_champion;
+        *self.slot_mut(from) = to_champion;
+    }
+
+    /// Moves the champion of `from` to `to`, replacing its previous champion and emptying `from`.
+    pub fn move_champion(&mut self, from: ChampionPosition, to: ChampionPosition) {
+        let champion = self.slot_mut(from).take();
+        *self.slot_mut(to) = champion;
+    }
+
+    /// Returns the slots of `other` that differ from this draft, revision excluded.
+    pub fn diff(&self, other: &Draft) -> Vec<ChampionUpdate> {
+        ChampionPosition::ALL
+            .into_iter()
+            .filter(|&position| self.get(position) != other.get(position))
+            .map(|position| ChampionUpdate {
+                champion_id: other.get(position),
+                position,
+            })
+            .collect()
+    }
+
+    pub fn roles(&self, team: Team) -> &[Option<ChampionRole>; 5] {
+        match team {
+            Team::Blue => &self.blue_roles,
+            Team::Red => &self.red_roles,
+        }
+    }
+
+    fn roles_mut(&mut self, team: Team) -> &mut [Option<ChampionRole>; 5] {
+        match team {
+            Team::Blue => &mut self.blue_roles,
+            Team::Red => &mut self.red_roles,
+        }
+    }
+
+    /// Assigns `role` to the pick at `position`, a `None` role clears it.
+    pub fn set_role(
+        &mut self,
+        position: ChampionPosition,
+        role: Option<ChampionRole>,
+    ) -> Result<(), DraftError> {
+        if position.action() != DraftAction::Pick {
+            return Err(DraftError::NotAPick { position });
+        }
+        if !self.settings.format.contains(position) {
+            return Err(DraftError::PositionNotInFormat { position });
+        }
+        self.roles_mut(position.team())[position.index()] = role;
+        Ok(())
+    }
+
+    /// Replaces every role of `team`.
+    pub fn set_roles(&mut self, team: Team, roles: [Option<ChampionRole>; 5]) {
+        *self.roles_mut(team) = roles;
+    }
+
+    /// Roles that no pick of `team` is assigned to.
+    pub fn missing_roles(&self, team: Team) -> Vec<ChampionRole> {
+        let roles = self.roles(team);
+        ChampionRole::ALL
+            .into_iter()
+            .filter(|role| !roles.contains(&Some(*role)))
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            revision: self.revision,
+            settings: self.settings.clone(),
+            locked_champions: self.locked_champions.clone(),
+            ..Self::default()
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Update {
+        #[serde(flatten)]
+        update: ChampionUpdate,
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    Swap {
+        from: ChampionPosition,
+        to: ChampionPosition,
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    Move {
+        from: ChampionPosition,
+        to: ChampionPosition,
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    Reset {
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    /// Asks the server for a full `DraftState`.
+    Snapshot,
+    /// Asks the server for the updates made after `last_revision`.
+    Resume {
+        last_revision: Revision,
+    },
+    /// Changes the display name and team shown to the other clients of the draft.
+    Identify {
+        name: Option<String>,
+        team: Option<Team>,
+    },
+    UpdateSettings {
+        settings: DraftSettings,
+    },
+    Undo {
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    Redo {
+        #[serde(default)]
+        expected_revision: Option<Revision>,
+    },
+    /// Shows the other clients which champion is considered for `position`, a `None` champion
+    /// clears the hover.
+    Hover {
+        position: ChampionPosition,
+        champion_id: Option<ChampionId>,
+    },
+    /// Makes the picks of the client team final in a blind draft.
+    LockIn,
+    /// Assigns the role played by the pick at `position`, a `None` role clears it.
+    AssignRole {
+        position: ChampionPosition,
+        role: Option<ChampionRole>,
+    },
+    /// Asks the server to assign the roles of the picks of `team` from the roles their champions
+    /// are played in.
+    AutoAssignRoles {
+        team: Team,
+    },
+    /// Proposes to exchange two picks of the client team during the trade phase.
+    ProposeTrade {
+        from: ChampionPosition,
+        to: ChampionPosition,
+    },
+    /// Accepts a trade proposed by another session of the client team, which applies it.
+    AcceptTrade {
+        trade_id: Uuid,
+    },
+    /// Declines a trade, or withdraws it when sent by the session that proposed it.
+    DeclineTrade {
+        trade_id: Uuid,
+    },
+    /// Marks the client team as ready, or not, to start the draft.
+    Ready {
+        ready: bool,
+    },
+    /// Makes the draft result final, only allowed to the draft creator, who joined with the
+    /// draft client id.
+    Lock,
+    Ping,
+}
+
+impl ClientMessage {
+    /// Whether the message leaves the draft untouched, the only messages accepted from
+    /// spectators.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            ClientMessage::Snapshot
+                | ClientMessage::Resume { .. }
+                | ClientMessage::Identify { .. }
+                | ClientMessage::Ping
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message of a socket, describing its own session.
+    Welcome {
+        session: Session,
+        /// Only sent to the clients that can edit both teams.
+        links: Option<DraftLinks>,
+        /// Trades proposed before the client joined and not resolved yet.
+        trades: Vec<TradeProposal>,
+    },
+    DraftState {
+        draft: Draft,
+    },
+    DraftDiff(DraftChanges),
+    /// Updates missed by a resuming client, oldest first.
+    Replay {
+        updates: Vec<DraftChanges>,
+    },
+    Error {
+        reason: String,
+    },
+    /// The edit was made against `expected_revision` but the draft is already at `revision`.
+    Conflict {
+        expected_revision: Revision,
+        revision: Revision,
+    },
+    SettingsChanged {
+        settings: DraftSettings,
+    },
+    TimerChanged {
+        timer: TimerState,
+    },
+    /// Next action of a tournament draft, `None` once the draft is complete.
+    TurnChanged {
+        turn: Option<Turn>,
+    },
+    /// Another session of the draft hovered a champion, or stopped hovering when `hover` is `None`.
+    Hovered {
+        session_id: SessionId,
+        hover: Option<ChampionUpdate>,
+    },
+    /// Clients connected to the draft, in join order.
+    PresenceChanged {
+        sessions: Vec<Session>,
+    },
+    /// A team of a blind draft locked in its picks.
+    LockedIn {
+        team: Team,
+    },
+    TradeProposed {
+        trade: TradeProposal,
+    },
+    LifecycleChanged {
+        lifecycle: DraftLifecycle,
+        ready_teams: Vec<Team>,
+    },
+    /// The trade was applied if `accepted`, declined or withdrawn otherwise.
+    TradeResolved {
+        trade_id: Uuid,
+        accepted: bool,
+    },
+    /// Roles of the picks of `team` changed, `missing_roles` are not played by any of them.
+    RolesChanged {
+        team: Team,
+        roles: [Option<ChampionRole>; 5],
+        missing_roles: Vec<ChampionRole>,
+    },
+    /// Champions picked in the previous games of the series changed.
+    LockedChampionsChanged {
+        locked_champions: Vec<ChampionId>,
+    },
+    /// Both teams of a blind draft locked in, `draft` shows every pick.
+    Revealed {
+        draft: Draft,
+    },
+    Ack,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ChampionPosition::*;
+    use DraftAction::{Ban, Pick};
+    use Team::{Blue, Red};
+
+    fn phase(action: DraftAction, teams: &[Team]) -> DraftPhase {
+        DraftPhase {
+            action,
+            teams: teams.to_vec(),
+        }
+    }
+
+    fn pick(position: ChampionPosition, champion_id: ChampionId) -> ChampionUpdate {
+        ChampionUpdate {
+            champion_id: Some(champion_id),
+            position,
+        }
+    }
+
+    #[test]
+    fn blind_picks_do_not_reveal_the_other_team() {
+        let mut draft = Draft {
+            settings: DraftSettings {
+                mode: DraftMode::Blind,
+                ..DraftSettings::default()
+            },
+            ..Draft::default()
+        };
+        assert_eq!(draft.try_update(&pick(Red3, 7)), Ok(()));
+        assert_eq!(draft.try_update(&pick(Blue1, 7)), Ok(()));
+        assert_eq!(
+            draft.try_update(&pick(Blue2, 7)),
+            Err(DraftError::DuplicateChampion {
+                champion_id: 7,
+                position: Blue1,
+            })
+        );
+
+        draft.settings.mode = DraftMode::Free;
+        draft.update(&pick(Blue1, 8));
+        assert_eq!(
+            draft.try_update(&pick(Blue1, 7)),
+            Err(DraftError::DuplicateChampion {
+                champion_id: 7,
+                position: Red3,
+            })
+        );
+    }
+
+    fn event(sequence: u64, revision: Revision, operation: DraftOperation) -> DraftEvent {
+        DraftEvent {
+            sequence,
+            revision,
+            session_id: None,
+            timestamp: 0,
+            operation,
         }
     }
+
+    #[test]
+    fn replay_rebuilds_every_operation() {
+        let roles = [Some(ChampionRole::MID), None, None, None, None];
+        let events = [
+            event(
+                1,
+                0,
+                DraftOperation::Settings {
+                    settings: DraftSettings {
+                        mode: DraftMode::Blind,
+                        ..DraftSettings::default()
+                    },
+                    step: 0,
+                },
+            ),
+            event(
+                2,
+                1,
+                DraftOperation::Update {
+                    changes: vec![pick(Blue1, 1), pick(Red1, 2)],
+                    step: 0,
+                },
+            ),
+            event(3, 1, DraftOperation::Roles { team: Blue, roles }),
+            event(4, 1, DraftOperation::LockIn { team: Red }),
+            event(
+                5,
+                2,
+                DraftOperation::Update {
+                    changes: vec![ChampionUpdate {
+                        champion_id: None,
+                        position: Blue1,
+                    }],
+                    step: 0,
+                },
+            ),
+            event(6, 3, DraftOperation::Reset),
+        ];
+
+        // events that keep the revision can still be rebuilt one by one
+        let draft = Draft::replay(&events, 2);
+        assert_eq!(draft.revision, 1);
+        assert_eq!(draft.settings.mode, DraftMode::Blind);
+        assert_eq!((draft.get(Blue1), draft.get(Red1)), (Some(1), Some(2)));
+        assert_eq!(draft.roles(Blue), &[None; 5]);
+        assert!(draft.locked_in.is_empty());
+
+        let draft = Draft::replay(&events, 3);
+        assert_eq!(draft.roles(Blue), &roles);
+        assert!(draft.locked_in.is_empty());
+
+        let draft = Draft::replay(&events, 4);
+        assert_eq!(draft.revision, 1);
+        assert_eq!(draft.locked_in, [Red]);
+
+        let draft = Draft::replay(&events, 5);
+        assert_eq!((draft.get(Blue1), draft.get(Red1)), (None, Some(2)));
+
+        let draft = Draft::replay(&events, 6);
+        assert_eq!(draft.revision, 3);
+        assert_eq!(draft.roles(Blue), &[None; 5]);
+        assert!(draft.locked_in.is_empty());
+        assert_eq!(draft.settings.mode, DraftMode::Blind);
+    }
+
+    #[test]
+    fn settings_are_rejected_if_the_board_breaks_them() {
+        let mut draft = Draft {
+            settings: DraftSettings {
+                mode: DraftMode::Blind,
+                ..DraftSettings::default()
+            },
+            ..Draft::default()
+        };
+        draft.update(&pick(Blue1, 7));
+        draft.update(&pick(Red1, 7));
+
+        let free = DraftSettings::default();
+        assert_eq!(
+            draft.set_settings(free.clone()),
+            Err(DraftError::DuplicateChampion {
+                champion_id: 7,
+                position: Red1,
+            })
+        );
+        assert_eq!(draft.settings.mode, DraftMode::Blind);
+
+        let duplicates = DraftSettings {
+            allow_duplicate_champions: true,
+            ..free
+        };
+        assert_eq!(draft.set_settings(duplicates), Ok(()));
+    }
+
+    #[test]
+    fn standard_format_order() {
+        let format = DraftFormat::default();
+        assert_eq!(format.validate(), Ok(()));
+        assert_eq!(
+            format.order(),
+            [
+                BlueBan1, RedBan1, BlueBan2, RedBan2, BlueBan3, RedBan3, Blue1, Red1, Red2, Blue2,
+                Blue3, Red3, RedBan4, BlueBan4, RedBan5, BlueBan5, Red4, Blue4, Blue5, Red5,
+            ]
+        );
+        assert_eq!(format.turn(20).map(|turn| turn.position), None);
+    }
+
+    #[test]
+    fn shorter_format_order() {
+        let format = DraftFormat {
+            picks_per_side: 3,
+            bans_per_side: 1,
+            phases: vec![
+                phase(Ban, &[Red, Blue]),
+                phase(Pick, &[Blue, Red, Red, Blue, Blue, Red]),
+            ],
+        };
+        assert_eq!(format.validate(), Ok(()));
+        assert_eq!(
+            format.order(),
+            [RedBan1, BlueBan1, Blue1, Red1, Red2, Blue2, Blue3, Red3]
+        );
+        assert!(format.contains(Blue3));
+        assert!(!format.contains(Blue4));
+        assert!(!format.contains(RedBan2));
+    }
+
+    #[test]
+    fn format_turns_must_fill_every_position_once() {
+        let mut format = DraftFormat {
+            picks_per_side: 1,
+            bans_per_side: 0,
+            phases: vec![phase(Pick, &[Blue])],
+        };
+        assert!(matches!(
+            format.validate(),
+            Err(DraftError::InvalidFormat(_))
+        ));
+
+        format.phases = vec![phase(Pick, &[Blue, Red, Red])];
+        assert!(matches!(
+            format.validate(),
+            Err(DraftError::InvalidFormat(_))
+        ));
+
+        format.phases = vec![phase(Pick, &[Red, Blue])];
+        assert_eq!(format.validate(), Ok(()));
+    }
+
+    #[test]
+    fn format_slots_are_limited() {
+        let format = DraftFormat {
+            picks_per_side: MAX_SIDE_SLOTS + 1,
+            bans_per_side: 0,
+            phases: vec![phase(Pick, &[Blue, Red].repeat(MAX_SIDE_SLOTS + 1))],
+        };
+        assert!(matches!(
+            format.validate(),
+            Err(DraftError::InvalidFormat(_))
+        ));
+    }
 }
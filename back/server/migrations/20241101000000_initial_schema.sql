@@ -1,4 +1,6 @@
-CREATE TABLE champion (
+-- Schema previously created by the init hook of the database container, kept as is so that
+-- existing databases are migrated from it.
+CREATE TABLE IF NOT EXISTS champion (
     id SERIAL PRIMARY KEY,
     riot_id VARCHAR(255) NOT NULL UNIQUE,
     name VARCHAR(255) NOT NULL UNIQUE,
@@ -7,7 +9,7 @@ CREATE TABLE champion (
     positions jsonb NOT NULL DEFAULT '[]'::jsonb
 );
 
-CREATE TABLE draft (
+CREATE TABLE IF NOT EXISTS draft (
     id SERIAL PRIMARY KEY,
     client_id uuid,
     blue_ban_1 INTEGER REFERENCES champion(id),
@@ -32,7 +34,7 @@ CREATE TABLE draft (
     red_5 INTEGER REFERENCES champion(id)
 );
 
-CREATE TABLE version (
+CREATE TABLE IF NOT EXISTS version (
     id SERIAL PRIMARY KEY,
     current VARCHAR(30)
-)
\ No newline at end of file
+);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS series (
+    id SERIAL PRIMARY KEY,
+    client_id uuid NOT NULL UNIQUE
+);
+
+ALTER TABLE draft
+    ADD COLUMN IF NOT EXISTS spectator_id uuid NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    ADD COLUMN IF NOT EXISTS blue_token uuid NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN IF NOT EXISTS red_token uuid NOT NULL DEFAULT gen_random_uuid(),
+    ADD COLUMN IF NOT EXISTS series_id INTEGER REFERENCES series(id),
+    ADD COLUMN IF NOT EXISTS series_game INTEGER,
+    ADD COLUMN IF NOT EXISTS settings jsonb NOT NULL DEFAULT '{}'::jsonb,
+    ADD COLUMN IF NOT EXISTS step INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS blue_roles jsonb NOT NULL DEFAULT '[null, null, null, null, null]'::jsonb,
+    ADD COLUMN IF NOT EXISTS red_roles jsonb NOT NULL DEFAULT '[null, null, null, null, null]'::jsonb,
+    ADD COLUMN IF NOT EXISTS lifecycle jsonb NOT NULL DEFAULT '"Lobby"'::jsonb;
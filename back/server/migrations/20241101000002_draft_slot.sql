@@ -0,0 +1,60 @@
+-- One row per position of a draft instead of one column per position.
+CREATE TABLE draft_slot (
+    draft_id INTEGER NOT NULL REFERENCES draft(id) ON DELETE CASCADE,
+    position VARCHAR(16) NOT NULL,
+    champion_id INTEGER REFERENCES champion(id),
+    role VARCHAR(16),
+    PRIMARY KEY (draft_id, position)
+);
+
+INSERT INTO draft_slot (draft_id, position, champion_id, role)
+SELECT draft.id, slot.position, slot.champion_id, slot.role
+FROM draft
+CROSS JOIN LATERAL (
+    VALUES
+        ('BlueBan1', draft.blue_ban_1, NULL),
+        ('BlueBan2', draft.blue_ban_2, NULL),
+        ('BlueBan3', draft.blue_ban_3, NULL),
+        ('BlueBan4', draft.blue_ban_4, NULL),
+        ('BlueBan5', draft.blue_ban_5, NULL),
+        ('RedBan1', draft.red_ban_1, NULL),
+        ('RedBan2', draft.red_ban_2, NULL),
+        ('RedBan3', draft.red_ban_3, NULL),
+        ('RedBan4', draft.red_ban_4, NULL),
+        ('RedBan5', draft.red_ban_5, NULL),
+        ('Blue1', draft.blue_1, draft.blue_roles->>0),
+        ('Blue2', draft.blue_2, draft.blue_roles->>1),
+        ('Blue3', draft.blue_3, draft.blue_roles->>2),
+        ('Blue4', draft.blue_4, draft.blue_roles->>3),
+        ('Blue5', draft.blue_5, draft.blue_roles->>4),
+        ('Red1', draft.red_1, draft.red_roles->>0),
+        ('Red2', draft.red_2, draft.red_roles->>1),
+        ('Red3', draft.red_3, draft.red_roles->>2),
+        ('Red4', draft.red_4, draft.red_roles->>3),
+        ('Red5', draft.red_5, draft.red_roles->>4)
+) AS slot(position, champion_id, role)
+WHERE slot.champion_id IS NOT NULL OR slot.role IS NOT NULL;
+
+ALTER TABLE draft
+    DROP COLUMN blue_ban_1,
+    DROP COLUMN blue_ban_2,
+    DROP COLUMN blue_ban_3,
+    DROP COLUMN blue_ban_4,
+    DROP COLUMN blue_ban_5,
+    DROP COLUMN red_ban_1,
+    DROP COLUMN red_ban_2,
+    DROP COLUMN red_ban_3,
+    DROP COLUMN red_ban_4,
+    DROP COLUMN red_ban_5,
+    DROP COLUMN blue_1,
+    DROP COLUMN blue_2,
+    DROP COLUMN blue_3,
+    DROP COLUMN blue_4,
+    DROP COLUMN blue_5,
+    DROP COLUMN red_1,
+    DROP COLUMN red_2,
+    DROP COLUMN red_3,
+    DROP COLUMN red_4,
+    DROP COLUMN red_5,
+    DROP COLUMN blue_roles,
+    DROP COLUMN red_roles;
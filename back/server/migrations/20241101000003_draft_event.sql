@@ -0,0 +1,11 @@
+-- Append-only log of the operations applied to each draft.
+CREATE TABLE draft_event (
+    id BIGSERIAL PRIMARY KEY,
+    draft_id INTEGER NOT NULL REFERENCES draft(id) ON DELETE CASCADE,
+    revision BIGINT NOT NULL,
+    session_id UUID,
+    created_at TIMESTAMPTZ NOT NULL,
+    operation JSONB NOT NULL
+);
+
+CREATE INDEX draft_event_draft_id ON draft_event (draft_id, id);
@@ -0,0 +1,2 @@
+-- Lets abandoned drafts be removed once they are no longer opened.
+ALTER TABLE draft ADD COLUMN last_accessed_at TIMESTAMPTZ NOT NULL DEFAULT now();
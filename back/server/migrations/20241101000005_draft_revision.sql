@@ -0,0 +1,2 @@
+-- Keeps revisions increasing across reloads, so resuming clients are not replayed a stale base.
+ALTER TABLE draft ADD COLUMN revision BIGINT NOT NULL DEFAULT 0;
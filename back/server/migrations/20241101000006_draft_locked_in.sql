@@ -0,0 +1,2 @@
+-- Teams that locked in their blind picks, so revealed drafts stay revealed after a reload.
+ALTER TABLE draft ADD COLUMN locked_in JSONB NOT NULL DEFAULT '[]';
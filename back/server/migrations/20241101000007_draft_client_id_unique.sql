@@ -0,0 +1,6 @@
+-- Concurrent loads of a new draft could insert it twice, only the first row is kept.
+DELETE FROM draft
+WHERE id NOT IN (SELECT MIN(id) FROM draft GROUP BY client_id)
+AND client_id IS NOT NULL;
+
+CREATE UNIQUE INDEX draft_client_id ON draft (client_id);
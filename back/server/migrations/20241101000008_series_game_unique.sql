@@ -0,0 +1,12 @@
+-- Concurrent additions of a game to a series could give two drafts the same game number, the
+-- games are numbered again in the order they were created.
+UPDATE draft
+SET series_game = numbered.series_game
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY series_id ORDER BY series_game, id) AS series_game
+    FROM draft
+    WHERE series_id IS NOT NULL
+) AS numbered
+WHERE draft.id = numbered.id;
+
+CREATE UNIQUE INDEX draft_series_game ON draft (series_id, series_game);
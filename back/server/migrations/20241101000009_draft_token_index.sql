@@ -0,0 +1,3 @@
+-- Captains join a draft with their token in place of its client id.
+CREATE INDEX IF NOT EXISTS draft_blue_token ON draft (blue_token);
+CREATE INDEX IF NOT EXISTS draft_red_token ON draft (red_token);
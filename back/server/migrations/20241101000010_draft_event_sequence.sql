@@ -0,0 +1,16 @@
+-- Events are ordered by a sequence number assigned by the server per draft, ids follow the order
+-- the inserts were committed in which may differ from the order the events were applied in.
+ALTER TABLE draft_event ADD COLUMN IF NOT EXISTS sequence BIGINT;
+
+UPDATE draft_event
+SET sequence = numbered.sequence
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY draft_id ORDER BY id) AS sequence
+    FROM draft_event
+) AS numbered
+WHERE draft_event.id = numbered.id;
+
+ALTER TABLE draft_event ALTER COLUMN sequence SET NOT NULL;
+
+DROP INDEX IF EXISTS draft_event_draft_id;
+CREATE UNIQUE INDEX draft_event_draft_id_sequence ON draft_event (draft_id, sequence);
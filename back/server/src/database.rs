@@ -1,6 +1,13 @@
-use draft_together_data::{Champion, ChampionRole, Draft};
+use draft_together_data::{
+    Champion, ChampionPosition, ChampionRole, ChampionUpdate, Draft, DraftAction, DraftEvent,
+    DraftLifecycle, DraftLinks, DraftOperation, DraftSettings, Team,
+};
 use semver::Version;
-use sqlx::{prelude::FromRow, query, query_as, types::Json, PgPool};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sqlx::{prelude::FromRow, query, query_as, types::Json, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::ServerDraft;
@@ -31,67 +38,98 @@ impl From<ChampionDatabase> for Champion {
 #[derive(Debug, FromRow)]
 pub struct DraftDatabase {
     pub id: i32,
-    pub client_id: Uuid,
-    pub blue_ban_1: Option<i32>,
-    pub blue_ban_2: Option<i32>,
-    pub blue_ban_3: Option<i32>,
-    pub blue_ban_4: Option<i32>,
-    pub blue_ban_5: Option<i32>,
-    pub red_ban_1: Option<i32>,
-    pub red_ban_2: Option<i32>,
-    pub red_ban_3: Option<i32>,
-    pub red_ban_4: Option<i32>,
-    pub red_ban_5: Option<i32>,
-    pub blue_1: Option<i32>,
-    pub blue_2: Option<i32>,
-    pub blue_3: Option<i32>,
-    pub blue_4: Option<i32>,
-    pub blue_5: Option<i32>,
-    pub red_1: Option<i32>,
-    pub red_2: Option<i32>,
-    pub red_3: Option<i32>,
-    pub red_4: Option<i32>,
-    pub red_5: Option<i32>,
+    pub spectator_id: Uuid,
+    pub blue_token: Uuid,
+    pub red_token: Uuid,
+    pub settings: Json<DraftSettings>,
+    pub step: i32,
+    pub revision: i64,
+    pub series_id: Option<i32>,
+    pub series_game: Option<i32>,
+    pub lifecycle: Json<DraftLifecycle>,
+    pub locked_in: Json<Vec<Team>>,
+    /// Filled by `query_draft_by_client_id` from the `draft_slot` table.
+    #[sqlx(skip)]
+    pub slots: Vec<DraftSlotDatabase>,
+}
+
+/// A position of a draft, only stored once it has a champion or a role.
+#[derive(Debug, Default, FromRow)]
+pub struct DraftSlotDatabase {
+    pub position: String,
+    pub champion_id: Option<i32>,
+    pub role: Option<String>,
 }
 
 impl From<DraftDatabase> for ServerDraft {
     fn from(value: DraftDatabase) -> Self {
-        Self {
-            id: value.id,
-            draft: Draft {
-                blue_champions: [
-                    value.blue_1,
-                    value.blue_2,
-                    value.blue_3,
-                    value.blue_4,
-                    value.blue_5,
-                ],
-                red_champions: [
-                    value.red_1,
-                    value.red_2,
-                    value.red_3,
-                    value.red_4,
-                    value.red_5,
-                ],
-                blue_bans: [
-                    value.blue_ban_1,
-                    value.blue_ban_2,
-                    value.blue_ban_3,
-                    value.blue_ban_4,
-                    value.blue_ban_5,
-                ],
-                red_bans: [
-                    value.red_ban_1,
-                    value.red_ban_2,
-                    value.red_ban_3,
-                    value.red_ban_4,
-                    value.red_ban_5,
-                ],
-            },
+        let id = value.id;
+        let links = DraftLinks {
+            spectator_id: value.spectator_id,
+            blue_token: value.blue_token,
+            red_token: value.red_token,
+        };
+        let lifecycle = match value.lifecycle.0 {
+            // which teams were ready is not saved, they have to ready up again
+            DraftLifecycle::Ready => DraftLifecycle::Lobby,
+            lifecycle => lifecycle,
+        };
+        let series = value.series_id.zip(value.series_game);
+        let mut server_draft = Self::new(id, links, value.into());
+        server_draft.lifecycle = lifecycle;
+        server_draft.series = series;
+        server_draft
+    }
+}
+
+impl From<DraftDatabase> for Draft {
+    fn from(value: DraftDatabase) -> Self {
+        let mut draft = Draft {
+            settings: value.settings.0,
+            step: value.step.try_into().unwrap_or_default(),
+            revision: value.revision.try_into().unwrap_or_default(),
+            locked_in: value.locked_in.0,
+            ..Draft::default()
+        };
+        for slot in value.slots {
+            let Some(position) = from_name::<ChampionPosition>(&slot.position) else {
+                warn!("unknown position {} in draft {}", slot.position, value.id);
+                continue;
+            };
+            draft.update(&ChampionUpdate {
+                champion_id: slot.champion_id,
+                position,
+            });
+            if position.action() == DraftAction::Pick {
+                let roles = match position.team() {
+                    Team::Blue => &mut draft.blue_roles,
+                    Team::Red => &mut draft.red_roles,
+                };
+                roles[position.index()] = slot.role.as_deref().and_then(from_name);
+            }
         }
+        draft
     }
 }
 
+/// Name of an enum variant as stored in the database, the name it is serialized with.
+fn to_name(value: impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => unreachable!("only unit variants are stored by name"),
+    }
+}
+
+fn from_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_value(Value::String(name.to_string())).ok()
+}
+
+#[derive(Debug, FromRow)]
+pub struct SeriesGameDatabase {
+    pub client_id: Uuid,
+    pub series_game: i32,
+}
+
 #[derive(Debug)]
 pub struct ChampionDatabaseInsertion {
     pub riot_id: String,
@@ -184,18 +222,165 @@ pub async fn query_draft_by_client_id(
     pool: &PgPool,
     id: Uuid,
 ) -> Result<DraftDatabase, sqlx::Error> {
-    query_as("SELECT * FROM draft WHERE client_id = $1")
+    let mut draft: DraftDatabase = query_as(
+        "SELECT id, spectator_id, blue_token, red_token, settings, step, revision, series_id, series_game, lifecycle, locked_in
+        FROM draft WHERE client_id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+    draft.slots =
+        query_as("SELECT position, champion_id, role FROM draft_slot WHERE draft_id = $1")
+            .bind(draft.id)
+            .fetch_all(pool)
+            .await?;
+
+    Ok(draft)
+}
+
+/// Inserts a new draft, returning its id and its generated links.
+pub async fn new_draft(pool: &PgPool, client_id: Uuid) -> Result<(i32, DraftLinks), sqlx::Error> {
+    let row: (i32, Uuid, Uuid, Uuid) = query_as(
+        "INSERT INTO draft (client_id) VALUES ($1)
+        ON CONFLICT (client_id) DO UPDATE SET client_id = EXCLUDED.client_id
+        RETURNING id, spectator_id, blue_token, red_token",
+    )
+    .bind(client_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((
+        row.0,
+        DraftLinks {
+            spectator_id: row.1,
+            blue_token: row.2,
+            red_token: row.3,
+        },
+    ))
+}
+
+/// Records that the draft was just opened, which postpones its expiry.
+pub async fn touch_draft(pool: &PgPool, id: i32) -> Result<(), sqlx::Error> {
+    query("UPDATE draft SET last_accessed_at = now() WHERE id = $1")
         .bind(id)
-        .fetch_one(pool)
-        .await
+        .execute(pool)
+        .await?;
+
+    Ok(())
 }
 
-pub async fn new_draft(pool: &PgPool, client_id: Uuid) -> Result<i32, sqlx::Error> {
-    let row: (i32,) = query_as("INSERT INTO draft (client_id) VALUES ($1) RETURNING id")
+/// Deletes the drafts not accessed for `empty_retention` without any champion or role, or for
+/// `retention` otherwise, except the drafts in `kept_client_ids`. Empty drafts of a series are
+/// kept, as its next games start empty. Returns the client ids of the deleted drafts.
+pub async fn delete_expired_drafts(
+    pool: &PgPool,
+    empty_retention: Duration,
+    retention: Duration,
+    kept_client_ids: &[Uuid],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let rows: Vec<(Uuid,)> = query_as(
+        "DELETE FROM draft
+        WHERE client_id <> ALL($3)
+        AND (
+            last_accessed_at < now() - make_interval(secs => $2)
+            OR (
+                last_accessed_at < now() - make_interval(secs => $1)
+                AND series_id IS NULL
+                AND NOT EXISTS (SELECT 1 FROM draft_slot WHERE draft_slot.draft_id = draft.id)
+            )
+        )
+        RETURNING client_id",
+    )
+    .bind(empty_retention.as_secs_f64())
+    .bind(retention.as_secs_f64())
+    .bind(kept_client_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.0).collect())
+}
+
+/// Finds the draft whose spectator id or captain token is `link`, returning its client id and
+/// its links.
+pub async fn query_draft_by_link(
+    pool: &PgPool,
+    link: Uuid,
+) -> Result<Option<(Uuid, DraftLinks)>, sqlx::Error> {
+    let row: Option<(Uuid, Uuid, Uuid, Uuid)> = query_as(
+        "SELECT client_id, spectator_id, blue_token, red_token FROM draft
+        WHERE spectator_id = $1 OR blue_token = $1 OR red_token = $1",
+    )
+    .bind(link)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(client_id, spectator_id, blue_token, red_token)| {
+        (
+            client_id,
+            DraftLinks {
+                spectator_id,
+                blue_token,
+                red_token,
+            },
+        )
+    }))
+}
+
+pub async fn query_series_id(pool: &PgPool, client_id: Uuid) -> Result<Option<i32>, sqlx::Error> {
+    let row: Option<(i32,)> = query_as("SELECT id FROM series WHERE client_id = $1")
         .bind(client_id)
-        .fetch_one(pool)
+        .fetch_optional(pool)
         .await?;
 
+    Ok(row.map(|row| row.0))
+}
+
+pub async fn new_series(pool: &PgPool, client_id: Uuid) -> Result<i32, sqlx::Error> {
+    let row: (i32,) = query_as(
+        "INSERT INTO series (client_id) VALUES ($1)
+        ON CONFLICT (client_id) DO UPDATE SET client_id = EXCLUDED.client_id
+        RETURNING id",
+    )
+    .bind(client_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.0)
+}
+
+pub async fn query_series_games(
+    pool: &PgPool,
+    series_id: i32,
+) -> Result<Vec<SeriesGameDatabase>, sqlx::Error> {
+    query_as("SELECT client_id, series_game FROM draft WHERE series_id = $1 ORDER BY series_game")
+        .bind(series_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Adds a draft as the next game of the series. The series is locked until the draft is inserted
+/// so that concurrent additions get different game numbers.
+pub async fn new_series_draft(
+    pool: &PgPool,
+    client_id: Uuid,
+    series_id: i32,
+) -> Result<i32, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    query("SELECT id FROM series WHERE id = $1 FOR UPDATE")
+        .bind(series_id)
+        .execute(&mut *transaction)
+        .await?;
+    let row: (i32,) = query_as(
+        "INSERT INTO draft (client_id, series_id, series_game)
+        VALUES ($1, $2, (SELECT COALESCE(MAX(series_game), 0) + 1 FROM draft WHERE series_id = $2))
+        RETURNING id",
+    )
+    .bind(client_id)
+    .bind(series_id)
+    .fetch_one(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
     Ok(row.0)
 }
 
@@ -208,55 +393,164 @@ pub async fn draft_exists(pool: &PgPool, client_id: Uuid) -> Result<bool, sqlx::
     Ok(result.is_some())
 }
 
-pub async fn update_draft(pool: &PgPool, server_draft: &ServerDraft) -> Result<(), sqlx::Error> {
-    let draft = &server_draft.draft;
+#[derive(Debug, FromRow)]
+pub struct DraftEventDatabase {
+    pub sequence: i64,
+    pub revision: i64,
+    pub session_id: Option<Uuid>,
+    pub timestamp: i64,
+    pub operation: Json<DraftOperation>,
+}
+
+impl From<DraftEventDatabase> for DraftEvent {
+    fn from(value: DraftEventDatabase) -> Self {
+        Self {
+            sequence: value.sequence.try_into().unwrap_or_default(),
+            revision: value.revision.try_into().unwrap_or_default(),
+            session_id: value.session_id,
+            timestamp: value.timestamp.try_into().unwrap_or_default(),
+            operation: value.operation.0,
+        }
+    }
+}
+
+/// Events of the draft, oldest first.
+pub async fn query_draft_events(
+    pool: &PgPool,
+    draft_id: i32,
+) -> Result<Vec<DraftEventDatabase>, sqlx::Error> {
+    query_as(
+        "SELECT sequence, revision, session_id, (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT AS timestamp, operation
+        FROM draft_event WHERE draft_id = $1 ORDER BY sequence",
+    )
+    .bind(draft_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// What is saved of a draft, copied out of the in-memory draft to write it without keeping the
+/// draft borrowed.
+#[derive(Debug)]
+pub struct DraftDatabaseUpdate {
+    pub id: i32,
+    pub draft: Draft,
+    pub lifecycle: DraftLifecycle,
+    /// Events recorded since the draft was last saved.
+    pub events: Vec<DraftEvent>,
+}
+
+impl DraftDatabaseUpdate {
+    /// Sequence of the last event written by the update, if any.
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.events.last().map(|event| event.sequence)
+    }
+}
+
+impl From<&ServerDraft> for DraftDatabaseUpdate {
+    fn from(value: &ServerDraft) -> Self {
+        Self {
+            id: value.id,
+            draft: value.draft.clone(),
+            lifecycle: value.lifecycle,
+            events: value.pending_events.clone(),
+        }
+    }
+}
+
+/// Sequence of the last event of the draft, 0 if it has none.
+pub async fn query_last_event_sequence(pool: &PgPool, draft_id: i32) -> Result<u64, sqlx::Error> {
+    let row: (Option<i64>,) = query_as("SELECT MAX(sequence) FROM draft_event WHERE draft_id = $1")
+        .bind(draft_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row
+        .0
+        .map_or(0, |sequence| sequence.try_into().unwrap_or_default()))
+}
+
+/// Saves all `drafts` in a single transaction, none of them is saved if one fails.
+pub async fn update_drafts(
+    pool: &PgPool,
+    drafts: &[DraftDatabaseUpdate],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    for draft in drafts {
+        write_draft(&mut transaction, draft).await?;
+    }
+    transaction.commit().await
+}
+
+async fn write_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    update: &DraftDatabaseUpdate,
+) -> Result<(), sqlx::Error> {
+    let draft = &update.draft;
     query(
         "UPDATE draft
-        SET blue_ban_1 = $1,
-        blue_ban_2 = $2,
-        blue_ban_3 =  $3,
-        blue_ban_4 = $4,
-        blue_ban_5 = $5,
-        red_ban_1 = $6,
-        red_ban_2 = $7,
-        red_ban_3 = $8,
-        red_ban_4 = $9,
-        red_ban_5 = $10,
-        blue_1 = $11,
-        blue_2 = $12,
-        blue_3 = $13,
-        blue_4 = $14,
-        blue_5 = $15,
-        red_1 = $16,
-        red_2 = $17,
-        red_3 = $18,
-        red_4 = $19,
-        red_5 = $20
-        WHERE id = $21",
+        SET settings = $1,
+        step = $2,
+        lifecycle = $3,
+        revision = $5,
+        locked_in = $6,
+        last_accessed_at = now()
+        WHERE id = $4",
+    )
+    .bind(Json(&draft.settings))
+    .bind(i32::try_from(draft.step).unwrap_or(i32::MAX))
+    .bind(Json(update.lifecycle))
+    .bind(update.id)
+    .bind(i64::try_from(draft.revision).unwrap_or(i64::MAX))
+    .bind(Json(&draft.locked_in))
+    .execute(&mut **transaction)
+    .await?;
+
+    let mut positions = Vec::new();
+    let mut champion_ids = Vec::new();
+    let mut roles = Vec::new();
+    for position in ChampionPosition::ALL {
+        let champion_id = draft.get(position);
+        let role = match position.action() {
+            DraftAction::Pick => draft.roles(position.team())[position.index()],
+            DraftAction::Ban => None,
+        };
+        if champion_id.is_some() || role.is_some() {
+            positions.push(to_name(position));
+            champion_ids.push(champion_id);
+            roles.push(role.map(to_name));
+        }
+    }
+    query("DELETE FROM draft_slot WHERE draft_id = $1")
+        .bind(update.id)
+        .execute(&mut **transaction)
+        .await?;
+    query(
+        "INSERT INTO draft_slot (draft_id, position, champion_id, role)
+        SELECT $1, * FROM UNNEST($2::VARCHAR[], $3::INTEGER[], $4::VARCHAR[])",
     )
-    .bind(draft.blue_bans[0])
-    .bind(draft.blue_bans[1])
-    .bind(draft.blue_bans[2])
-    .bind(draft.blue_bans[3])
-    .bind(draft.blue_bans[4])
-    .bind(draft.red_bans[0])
-    .bind(draft.red_bans[1])
-    .bind(draft.red_bans[2])
-    .bind(draft.red_bans[3])
-    .bind(draft.red_bans[4])
-    .bind(draft.blue_champions[0])
-    .bind(draft.blue_champions[1])
-    .bind(draft.blue_champions[2])
-    .bind(draft.blue_champions[3])
-    .bind(draft.blue_champions[4])
-    .bind(draft.red_champions[0])
-    .bind(draft.red_champions[1])
-    .bind(draft.red_champions[2])
-    .bind(draft.red_champions[3])
-    .bind(draft.red_champions[4])
-    .bind(server_draft.id)
-    .execute(pool)
+    .bind(update.id)
+    .bind(positions)
+    .bind(champion_ids)
+    .bind(roles)
+    .execute(&mut **transaction)
     .await?;
 
+    // the events are kept in memory until the save succeeds, another save may have written them
+    for event in &update.events {
+        query(
+            "INSERT INTO draft_event (draft_id, sequence, revision, session_id, created_at, operation)
+            VALUES ($1, $2, $3, $4, to_timestamp($5::DOUBLE PRECISION / 1000), $6)
+            ON CONFLICT (draft_id, sequence) DO NOTHING",
+        )
+        .bind(update.id)
+        .bind(i64::try_from(event.sequence).unwrap_or(i64::MAX))
+        .bind(i64::try_from(event.revision).unwrap_or(i64::MAX))
+        .bind(event.session_id)
+        .bind(event.timestamp as f64)
+        .bind(Json(&event.operation))
+        .execute(&mut **transaction)
+        .await?;
+    }
+
     Ok(())
 }
@@ -2,7 +2,7 @@ use axum::{
     extract::{self, State},
     http::StatusCode,
     response::IntoResponse,
-    routing::{any, get},
+    routing::{any, get, post},
     Json, Router,
 };
 use dashmap::{
@@ -10,15 +10,30 @@ use dashmap::{
     DashMap,
 };
 use database::ChampionDatabaseInsertion;
-use draft_together_data::{Champion, Draft};
+use draft_together_data::{
+    Champion, ChampionId, ChampionPosition, ChampionUpdate, Draft, DraftAction, DraftChanges,
+    DraftError, DraftEvent, DraftLifecycle, DraftLinks, DraftMode, DraftOperation, Revision,
+    Series, Session, SessionId, Team, TradeProposal,
+};
 use league_data::DATA_DRAGON_DIR;
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use tokio::sync::{broadcast, RwLock};
+use timer::TurnTimer;
+use tokio::{
+    signal,
+    sync::{broadcast, RwLock},
+};
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
-use ws::WsEvent;
+use ws::{Access, WsEvent};
 
-use std::{collections::HashSet, env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{HashSet, VecDeque},
+    env,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tower_http::{
     services::ServeDir,
     trace::{DefaultMakeSpan, TraceLayer},
@@ -31,19 +46,26 @@ use anyhow::Result;
 mod community_data;
 mod database;
 mod league_data;
+mod roles;
+mod timer;
 mod ws;
 
 #[derive(thiserror::Error, Debug)]
 enum ApiError {
     #[error("error while fetching database: {0}")]
     Database(#[from] sqlx::Error),
+    #[error("resource not found")]
+    NotFound,
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
-        error!("an error has occured while fetching api: {self}");
         match self {
-            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            Self::Database(_) => {
+                error!("an error has occured while fetching api: {self}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+            Self::NotFound => StatusCode::NOT_FOUND.into_response(),
         }
     }
 }
@@ -52,21 +74,291 @@ impl IntoResponse for ApiError {
 struct AppState {
     pool: Arc<PgPool>,
     drafts: Arc<DashMap<Uuid, ServerDraft>>,
-    drafts_connected_clients: Arc<DashMap<Uuid, u32>>,
+    drafts_sessions: Arc<DashMap<Uuid, Vec<Session>>>,
     valid_champion_ids: Arc<RwLock<HashSet<i32>>>,
-    events_sender: Arc<broadcast::Sender<WsEvent>>,
-    _events_receiver: Arc<broadcast::Receiver<WsEvent>>,
+}
+
+const DRAFT_EVENTS_CAPACITY: usize = 16;
+const RECENT_UPDATES_CAPACITY: usize = 64;
+const HISTORY_CAPACITY: usize = 32;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const SOCKETS_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_EMPTY_DRAFT_RETENTION: Duration = Duration::from_secs(60 * 60 * 24);
+const DEFAULT_DRAFT_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 180);
+
+/// Slots changed by an edit, with their champions before and after it.
+#[derive(Debug, Clone)]
+struct HistoryEntry {
+    before: Vec<ChampionUpdate>,
+    after: Vec<ChampionUpdate>,
 }
 
 #[derive(Debug)]
 struct ServerDraft {
     id: i32,
+    links: DraftLinks,
     draft: Draft,
+    events_sender: broadcast::Sender<WsEvent>,
+    recent_updates: VecDeque<DraftChanges>,
+    undo_history: VecDeque<HistoryEntry>,
+    redo_history: Vec<HistoryEntry>,
+    timer: Option<TurnTimer>,
+    /// Trades waiting to be accepted.
+    trades: Vec<TradeProposal>,
+    lifecycle: DraftLifecycle,
+    ready_teams: Vec<Team>,
+    /// Changed since it was last saved to the database.
+    dirty: bool,
+    /// Id of the series and number of the game, if the draft is a game of a series.
+    series: Option<(i32, i32)>,
+    /// Events recorded since the draft was last saved, they are written along with it.
+    pending_events: Vec<DraftEvent>,
+    /// Sequence of the last recorded event.
+    last_sequence: u64,
 }
 
 impl ServerDraft {
-    pub fn new(id: i32, draft: Draft) -> Self {
-        Self { id, draft }
+    pub fn new(id: i32, links: DraftLinks, draft: Draft) -> Self {
+        let (events_sender, _) = broadcast::channel(DRAFT_EVENTS_CAPACITY);
+        Self {
+            id,
+            links,
+            draft,
+            events_sender,
+            recent_updates: VecDeque::with_capacity(RECENT_UPDATES_CAPACITY),
+            undo_history: VecDeque::with_capacity(HISTORY_CAPACITY),
+            redo_history: Vec::new(),
+            timer: None,
+            trades: Vec::new(),
+            lifecycle: DraftLifecycle::default(),
+            ready_teams: Vec::new(),
+            dirty: false,
+            series: None,
+            pending_events: Vec::new(),
+            last_sequence: 0,
+        }
+    }
+
+    /// Errors if the draft can not be edited in its current lifecycle state.
+    pub fn ensure_editable(&self) -> Result<(), DraftError> {
+        match self.lifecycle {
+            DraftLifecycle::Locked => Err(DraftError::DraftLocked),
+            DraftLifecycle::Lobby if self.draft.settings.mode != DraftMode::Free => {
+                Err(DraftError::NotStarted)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks `team` as ready or not, the draft is ready to start once both teams are.
+    pub fn set_ready(&mut self, team: Team, ready: bool) -> Result<(), DraftError> {
+        if !matches!(
+            self.lifecycle,
+            DraftLifecycle::Lobby | DraftLifecycle::Ready
+        ) {
+            return Err(DraftError::AlreadyStarted);
+        }
+        self.ready_teams.retain(|&ready_team| ready_team != team);
+        if ready {
+            self.ready_teams.push(team);
+        }
+        self.lifecycle = if self.ready_teams.len() == 2 {
+            DraftLifecycle::Ready
+        } else {
+            DraftLifecycle::Lobby
+        };
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Makes the draft result final.
+    pub fn lock(&mut self) -> Result<(), DraftError> {
+        if self.lifecycle == DraftLifecycle::Locked {
+            return Err(DraftError::DraftLocked);
+        }
+        self.lifecycle = DraftLifecycle::Locked;
+        self.trades.clear();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Appends `operation`, made by `session_id`, to the event log of the draft at its current
+    /// revision. Recorded while the draft is borrowed, so that the log follows the order the
+    /// operations were applied in.
+    pub fn record(&mut self, session_id: Option<SessionId>, operation: DraftOperation) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.last_sequence += 1;
+        self.pending_events.push(DraftEvent {
+            sequence: self.last_sequence,
+            revision: self.draft.revision,
+            session_id,
+            timestamp,
+            operation,
+        });
+        self.dirty = true;
+    }
+
+    /// Forgets the pending events up to `sequence` once they were saved.
+    pub fn events_saved(&mut self, sequence: Option<u64>) {
+        if let Some(sequence) = sequence {
+            self.pending_events
+                .retain(|event| event.sequence > sequence);
+        }
+    }
+
+    /// Moves an edited draft to in progress, or to completed once every action is done.
+    pub fn update_lifecycle(&mut self) {
+        if self.lifecycle == DraftLifecycle::Locked {
+            return;
+        }
+        self.lifecycle = if self.draft.is_complete() {
+            DraftLifecycle::Completed
+        } else {
+            DraftLifecycle::InProgress
+        };
+    }
+
+    /// Applies `edit` to the draft and bumps its revision. The returned changes are empty, and
+    /// the revision is kept, if the edit did not change any slot. A failed edit is rolled back.
+    pub fn edit(
+        &mut self,
+        edit: impl FnOnce(&mut Draft) -> Result<(), DraftError>,
+    ) -> Result<DraftChanges, DraftError> {
+        let previous = self.draft.clone();
+        if let Err(e) = edit(&mut self.draft) {
+            self.draft = previous;
+            return Err(e);
+        }
+        let update = self.commit(&previous);
+        self.push_history(&previous, &update);
+        Ok(update)
+    }
+
+    /// Clears the picks, bans, roles and lock-ins of the draft, keeping its settings. Unlike an
+    /// edit, the revision is bumped even if no slot changed. Returns the changed slots and the
+    /// pending trades, which are dropped.
+    pub fn reset(&mut self) -> (DraftChanges, Vec<TradeProposal>) {
+        let previous = self.draft.clone();
+        self.draft.reset();
+        self.draft.revision += 1;
+        self.dirty = true;
+        self.update_lifecycle();
+        let update = DraftChanges {
+            changes: previous.diff(&self.draft),
+            revision: self.draft.revision,
+        };
+        self.record_update(update.clone());
+        self.push_history(&previous, &update);
+        (update, std::mem::take(&mut self.trades))
+    }
+
+    fn push_history(&mut self, previous: &Draft, update: &DraftChanges) {
+        if update.changes.is_empty() {
+            return;
+        }
+        if self.undo_history.len() == HISTORY_CAPACITY {
+            self.undo_history.pop_front();
+        }
+        self.undo_history.push_back(HistoryEntry {
+            before: self.draft.diff(previous),
+            after: update.changes.clone(),
+        });
+        self.redo_history.clear();
+    }
+
+    /// Reverts the last edit, `None` if there is nothing to undo. The edit stays in the history
+    /// if reverting it would make the draft invalid.
+    pub fn undo(&mut self) -> Option<Result<DraftChanges, DraftError>> {
+        let entry = self.undo_history.pop_back()?;
+        let result = self.apply_history(&entry.before);
+        if result.is_ok() {
+            self.redo_history.push(entry);
+        } else {
+            self.undo_history.push_back(entry);
+        }
+        Some(result)
+    }
+
+    /// Applies again the last undone edit, `None` if there is nothing to redo. The edit stays in
+    /// the history if applying it would make the draft invalid.
+    pub fn redo(&mut self) -> Option<Result<DraftChanges, DraftError>> {
+        let entry = self.redo_history.pop()?;
+        let result = self.apply_history(&entry.after);
+        if result.is_ok() {
+            self.undo_history.push_back(entry);
+        } else {
+            self.redo_history.push(entry);
+        }
+        Some(result)
+    }
+
+    fn apply_history(&mut self, slots: &[ChampionUpdate]) -> Result<DraftChanges, DraftError> {
+        let previous = self.draft.clone();
+        for slot in slots {
+            self.draft.update(slot);
+        }
+        if let Err(e) = slots
+            .iter()
+            .try_for_each(|slot| self.draft.validate_position(slot.position))
+        {
+            self.draft = previous;
+            return Err(e);
+        }
+        Ok(self.commit(&previous))
+    }
+
+    fn commit(&mut self, previous: &Draft) -> DraftChanges {
+        let changes = previous.diff(&self.draft);
+        if changes.is_empty() {
+            return DraftChanges {
+                changes,
+                revision: self.draft.revision,
+            };
+        }
+
+        self.draft.revision += 1;
+        self.dirty = true;
+        self.update_lifecycle();
+        let update = DraftChanges {
+            changes,
+            revision: self.draft.revision,
+        };
+        self.record_update(update.clone());
+        update
+    }
+
+    fn record_update(&mut self, update: DraftChanges) {
+        if self.recent_updates.len() == RECENT_UPDATES_CAPACITY {
+            self.recent_updates.pop_front();
+        }
+        self.recent_updates.push_back(update);
+    }
+
+    /// Returns the updates made after `last_revision`, or `None` if some of them are no longer
+    /// in the recent updates and a full snapshot is needed.
+    pub fn updates_since(&self, last_revision: Revision) -> Option<Vec<DraftChanges>> {
+        if last_revision > self.draft.revision {
+            return None;
+        }
+        if last_revision == self.draft.revision {
+            return Some(Vec::new());
+        }
+
+        let oldest_revision = self.recent_updates.front()?.revision;
+        if last_revision + 1 < oldest_revision {
+            return None;
+        }
+
+        Some(
+            self.recent_updates
+                .iter()
+                .filter(|update| update.revision > last_revision)
+                .cloned()
+                .collect(),
+        )
     }
 }
 
@@ -89,18 +381,17 @@ async fn main() {
         ))
         .await
         .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    info!("database migrations applied");
 
     let champions = database::query_champions(&pool).await.unwrap();
     let valid_champion_ids = champions.iter().map(|champion| champion.id).collect();
 
-    let (draft_tx, draft_rx) = broadcast::channel(16);
     let app_state = AppState {
         pool: Arc::new(pool),
         valid_champion_ids: Arc::new(RwLock::new(valid_champion_ids)),
-        _events_receiver: Arc::new(draft_rx),
-        events_sender: Arc::new(draft_tx),
         drafts: Arc::new(DashMap::new()),
-        drafts_connected_clients: Arc::new(DashMap::default()),
+        drafts_sessions: Arc::new(DashMap::default()),
     };
 
     {
@@ -128,22 +419,31 @@ async fn main() {
         });
     }
 
+    tokio::spawn(timer::run_turn_timers(app_state.clone()));
+
     {
         let app_state = app_state.clone();
+        let flush_interval = duration_from_env("DRAFT_FLUSH_INTERVAL_SECS", DEFAULT_FLUSH_INTERVAL);
         tokio::spawn(async move {
             loop {
-                for draft in app_state.drafts.iter() {
-                    if let Err(e) = database::update_draft(&app_state.pool, &draft).await {
-                        error!("failed to update draft with id {}: {e}", draft.id);
-                    } else {
-                        info!(
-                            "draft with id: {} was successfully saved into database",
-                            draft.id
-                        );
-                    }
-                }
+                tokio::time::sleep(flush_interval).await;
+                flush_dirty_drafts(&app_state).await;
+            }
+        });
+    }
 
-                tokio::time::sleep(Duration::from_secs(30)).await
+    {
+        let app_state = app_state.clone();
+        let empty_retention =
+            duration_from_env("EMPTY_DRAFT_RETENTION_SECS", DEFAULT_EMPTY_DRAFT_RETENTION);
+        let retention = duration_from_env("DRAFT_RETENTION_SECS", DEFAULT_DRAFT_RETENTION);
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = delete_expired_drafts(&app_state, empty_retention, retention).await
+                {
+                    error!("error while deleting expired drafts: {e}");
+                }
+                tokio::time::sleep(CLEANUP_INTERVAL).await
             }
         });
     }
@@ -153,12 +453,19 @@ async fn main() {
         .fallback_service(ServeDir::new(assets_dir).append_index_html_on_directories(true))
         .route("/ws/:draft_client_id", any(ws::ws_handler))
         .route("/draft/:client_id", get(get_draft))
+        .route("/draft/:client_id/history", get(get_draft_history))
+        .route(
+            "/draft/:client_id/history/:sequence",
+            get(get_draft_at_event),
+        )
+        .route("/series/:client_id", get(get_series))
+        .route("/series/:client_id/games", post(new_series_game))
         .route("/champions", get(get_champions))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
-        .with_state(app_state);
+        .with_state(app_state.clone());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
@@ -166,8 +473,141 @@ async fn main() {
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal(app_state.clone()))
     .await
     .unwrap();
+
+    // the sockets save their draft when they close, the remaining drafts are saved here
+    let sockets_closed = tokio::time::timeout(SOCKETS_CLOSE_TIMEOUT, async {
+        while !app_state.drafts_sessions.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if sockets_closed.is_err() {
+        warn!("some web sockets were still open after {SOCKETS_CLOSE_TIMEOUT:?}");
+    }
+    flush_dirty_drafts(&app_state).await;
+    info!("server stopped");
+}
+
+/// Resolves on SIGINT or SIGTERM, once every socket was asked to close.
+async fn shutdown_signal(app_state: AppState) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("shutting down, closing web sockets");
+    for server_draft in app_state.drafts.iter() {
+        ws::broadcast(&server_draft.events_sender, WsEvent::Shutdown);
+    }
+}
+
+/// Reads a number of seconds from the environment variable `name`, `default` if it is not set.
+/// Zero is rejected like an invalid value, it would make the periodic tasks run in a busy loop.
+fn duration_from_env(name: &str, default: Duration) -> Duration {
+    let Ok(secs) = env::var(name) else {
+        return default;
+    };
+    match secs.parse() {
+        Ok(secs) if secs > 0 => Duration::from_secs(secs),
+        _ => {
+            warn!(
+                "{name} must be a positive number of seconds, got `{secs}`, using {}",
+                default.as_secs()
+            );
+            default
+        }
+    }
+}
+
+/// Deletes the drafts no longer opened, see `database::delete_expired_drafts`. Drafts with
+/// connected clients are kept, and the deleted drafts are also removed from memory.
+async fn delete_expired_drafts(
+    app_state: &AppState,
+    empty_retention: Duration,
+    retention: Duration,
+) -> Result<(), sqlx::Error> {
+    let connected_drafts: Vec<Uuid> = app_state
+        .drafts_sessions
+        .iter()
+        .map(|sessions| *sessions.key())
+        .collect();
+    let deleted = database::delete_expired_drafts(
+        &app_state.pool,
+        empty_retention,
+        retention,
+        &connected_drafts,
+    )
+    .await?;
+    for client_id in &deleted {
+        app_state.drafts.remove(client_id);
+    }
+    info!(
+        "{} expired drafts were deleted from database",
+        deleted.len()
+    );
+    Ok(())
+}
+
+/// Saves the drafts changed since their last save in a single transaction. They are kept dirty
+/// to be saved again on the next flush if the transaction fails.
+async fn flush_dirty_drafts(app_state: &AppState) {
+    let updates: Vec<database::DraftDatabaseUpdate> = app_state
+        .drafts
+        .iter_mut()
+        .filter(|server_draft| server_draft.dirty)
+        .map(|mut server_draft| {
+            server_draft.dirty = false;
+            (&*server_draft).into()
+        })
+        .collect();
+    if updates.is_empty() {
+        return;
+    }
+
+    match database::update_drafts(&app_state.pool, &updates).await {
+        Ok(()) => {
+            for update in &updates {
+                if let Some(mut server_draft) = app_state
+                    .drafts
+                    .iter_mut()
+                    .find(|draft| draft.id == update.id)
+                {
+                    server_draft.events_saved(update.last_sequence());
+                }
+            }
+            debug!("{} changed drafts were saved into database", updates.len());
+        }
+        Err(e) => {
+            error!("failed to save {} changed drafts: {e}", updates.len());
+            for update in &updates {
+                if let Some(mut server_draft) = app_state
+                    .drafts
+                    .iter_mut()
+                    .find(|draft| draft.id == update.id)
+                {
+                    server_draft.dirty = true;
+                }
+            }
+        }
+    }
 }
 
 async fn update_riot_data(app_state: &AppState) -> Result<()> {
@@ -306,12 +746,95 @@ async fn get_draft(
     extract::Path(client_id): extract::Path<Uuid>,
     State(app_state): State<AppState>,
 ) -> Result<Json<Draft>, ApiError> {
+    let (client_id, _) = resolve_draft_id(&app_state, client_id).await?;
     get_current_draft(&app_state, client_id)
         .await
-        .map(|server_draft| Json(server_draft.draft.clone()))
+        .map(|server_draft| Json(server_draft.draft.view_for(None)))
         .map_err(|e| e.into())
 }
 
+/// Event log of the draft, oldest first, without the picks still hidden from spectators.
+async fn get_draft_history(
+    extract::Path(client_id): extract::Path<Uuid>,
+    State(app_state): State<AppState>,
+) -> Result<Json<Vec<DraftEvent>>, ApiError> {
+    let (draft, events) = query_draft_log(&app_state, client_id).await?;
+    let events = events
+        .into_iter()
+        .filter_map(|mut event| {
+            match &mut event.operation {
+                DraftOperation::Update { changes, .. } => {
+                    changes.retain(|change| !draft.is_hidden_from(change.position, None));
+                }
+                DraftOperation::Roles { team, .. } => {
+                    let hidden = ChampionPosition::new(*team, DraftAction::Pick, 0)
+                        .is_some_and(|position| draft.is_hidden_from(position, None));
+                    if hidden {
+                        return None;
+                    }
+                }
+                _ => {}
+            }
+            Some(event)
+        })
+        .collect();
+
+    Ok(Json(events))
+}
+
+/// The draft rebuilt from its event log as it was once the event `sequence` was applied.
+async fn get_draft_at_event(
+    extract::Path((client_id, sequence)): extract::Path<(Uuid, u64)>,
+    State(app_state): State<AppState>,
+) -> Result<Json<Draft>, ApiError> {
+    let (draft, events) = query_draft_log(&app_state, client_id).await?;
+    let rebuilt = Draft::replay(&events, sequence);
+
+    // picks are hidden as long as they are hidden in the current draft
+    Ok(Json(rebuilt.view_hidden_like(&draft, None)))
+}
+
+/// The current draft joined with `id` and its event log, oldest event first. The draft is not
+/// loaded if no client is editing it, an unknown draft is not found.
+async fn query_draft_log(
+    app_state: &AppState,
+    id: Uuid,
+) -> Result<(Draft, Vec<DraftEvent>), ApiError> {
+    let (client_id, _) = resolve_draft_id(app_state, id).await?;
+    let loaded = app_state.drafts.get(&client_id).map(|server_draft| {
+        (
+            server_draft.id,
+            server_draft.draft.clone(),
+            server_draft.pending_events.clone(),
+        )
+    });
+    let (id, draft, pending_events) = match loaded {
+        Some(loaded) => loaded,
+        None => {
+            if !database::draft_exists(&app_state.pool, client_id).await? {
+                return Err(ApiError::NotFound);
+            }
+            let draft_database =
+                database::query_draft_by_client_id(&app_state.pool, client_id).await?;
+            (draft_database.id, draft_database.into(), Vec::new())
+        }
+    };
+
+    let mut events: Vec<DraftEvent> = database::query_draft_events(&app_state.pool, id)
+        .await?
+        .into_iter()
+        .map(|event| event.into())
+        .collect();
+    // the pending events may have been saved since they were copied
+    let last_saved = events.last().map_or(0, |event| event.sequence);
+    events.extend(
+        pending_events
+            .into_iter()
+            .filter(|event| event.sequence > last_saved),
+    );
+    Ok((draft, events))
+}
+
 async fn get_champions(State(app_state): State<AppState>) -> Result<Json<Vec<Champion>>, ApiError> {
     let champions = database::query_champions(&app_state.pool).await?;
     let champions = champions
@@ -326,56 +849,269 @@ async fn get_current_draft(
     app_state: &AppState,
     draft_id: Uuid,
 ) -> Result<Ref<'_, Uuid, ServerDraft>, sqlx::Error> {
-    let draft = app_state.drafts.get(&draft_id);
-
-    if let Some(draft) = draft {
-        Ok(draft)
-    } else if database::draft_exists(&app_state.pool, draft_id).await? {
-        let draft = database::query_draft_by_client_id(&app_state.pool, draft_id)
-            .await?
-            .into();
-        app_state.drafts.insert(draft_id, draft);
-        Ok(app_state
-            .drafts
-            .get(&draft_id)
-            .expect("draft should be in the dashmap, as it was just inserted"))
-    } else {
-        let id = database::new_draft(&app_state.pool, draft_id).await?;
-        let draft = Draft::default();
-        let server_draft = ServerDraft::new(id, draft);
-        app_state.drafts.insert(draft_id, server_draft);
-        Ok(app_state
-            .drafts
-            .get(&draft_id)
-            .expect("draft should be in the dashmap, as it was just inserted"))
+    if let Some(draft) = app_state.drafts.get(&draft_id) {
+        return Ok(draft);
     }
+
+    let server_draft = load_draft(app_state, draft_id).await?;
+    // another client may have loaded the draft meanwhile, its events channel is kept
+    Ok(app_state
+        .drafts
+        .entry(draft_id)
+        .or_insert(server_draft)
+        .downgrade())
 }
 
 async fn get_current_draft_mut(
     app_state: &AppState,
     draft_id: Uuid,
 ) -> Result<RefMut<'_, Uuid, ServerDraft>, sqlx::Error> {
-    let draft = app_state.drafts.get_mut(&draft_id);
-
-    if let Some(draft) = draft {
-        Ok(draft)
-    } else if database::draft_exists(&app_state.pool, draft_id).await? {
-        let draft = database::query_draft_by_client_id(&app_state.pool, draft_id)
-            .await?
-            .into();
-        app_state.drafts.insert(draft_id, draft);
-        Ok(app_state
-            .drafts
-            .get_mut(&draft_id)
-            .expect("draft should be in the dashmap, as it was just inserted"))
-    } else {
-        let id = database::new_draft(&app_state.pool, draft_id).await?;
-        let draft = Draft::default();
-        let server_draft = ServerDraft::new(id, draft);
-        app_state.drafts.insert(draft_id, server_draft);
-        Ok(app_state
-            .drafts
-            .get_mut(&draft_id)
-            .expect("draft should be in the dashmap, as it was just inserted"))
+    if let Some(draft) = app_state.drafts.get_mut(&draft_id) {
+        return Ok(draft);
+    }
+
+    let server_draft = load_draft(app_state, draft_id).await?;
+    // another client may have loaded the draft meanwhile, its events channel is kept
+    Ok(app_state.drafts.entry(draft_id).or_insert(server_draft))
+}
+
+/// Finds the client id of the draft joined with `id`, which is either its client id, its
+/// spectator id or one of its captain tokens. An unknown id is the client id of a draft that does
+/// not exist yet.
+async fn resolve_draft_id(app_state: &AppState, id: Uuid) -> Result<(Uuid, Access), sqlx::Error> {
+    if app_state.drafts.contains_key(&id) {
+        return Ok((id, Access::Editor));
+    }
+    if let Some(draft) = app_state
+        .drafts
+        .iter()
+        .find(|draft| draft.links.spectator_id == id || draft.links.captain_team(id).is_some())
+    {
+        return Ok((*draft.key(), link_access(&draft.links, id)));
+    }
+    match database::query_draft_by_link(&app_state.pool, id).await? {
+        Some((client_id, links)) => Ok((client_id, link_access(&links, id))),
+        None => Ok((id, Access::Editor)),
+    }
+}
+
+/// Access given by `link`, the spectator id or a captain token of a draft.
+fn link_access(links: &DraftLinks, link: Uuid) -> Access {
+    links
+        .captain_team(link)
+        .map_or(Access::Spectator, Access::Captain)
+}
+
+/// Loads the draft from the database, or creates it if it does not exist yet.
+async fn load_draft(app_state: &AppState, draft_id: Uuid) -> Result<ServerDraft, sqlx::Error> {
+    if !database::draft_exists(&app_state.pool, draft_id).await? {
+        let (id, links) = database::new_draft(&app_state.pool, draft_id).await?;
+        return Ok(ServerDraft::new(id, links, Draft::default()));
+    }
+
+    let draft_database = database::query_draft_by_client_id(&app_state.pool, draft_id).await?;
+    database::touch_draft(&app_state.pool, draft_database.id).await?;
+    let mut server_draft: ServerDraft = draft_database.into();
+    server_draft.last_sequence =
+        database::query_last_event_sequence(&app_state.pool, server_draft.id).await?;
+    if let Some((series_id, series_game)) = server_draft.series {
+        server_draft.draft.locked_champions =
+            series_locked_champions(app_state, series_id, Some(series_game)).await?;
+    }
+    Ok(server_draft)
+}
+
+/// Champions picked in the games of the series played before `before_game`, or in all of its
+/// games if `None`.
+async fn series_locked_champions(
+    app_state: &AppState,
+    series_id: i32,
+    before_game: Option<i32>,
+) -> Result<Vec<ChampionId>, sqlx::Error> {
+    let games = database::query_series_games(&app_state.pool, series_id).await?;
+    let mut locked_champions = Vec::new();
+    for game in games {
+        if before_game.is_some_and(|before_game| game.series_game >= before_game) {
+            break;
+        }
+        // the game may still be edited, in which case the database is not up to date
+        let picks: Vec<ChampionId> = match app_state.drafts.get(&game.client_id) {
+            Some(server_draft) => server_draft.draft.picks().collect(),
+            None => {
+                let draft: Draft =
+                    database::query_draft_by_client_id(&app_state.pool, game.client_id)
+                        .await?
+                        .into();
+                draft.picks().collect()
+            }
+        };
+        for champion_id in picks {
+            if !locked_champions.contains(&champion_id) {
+                locked_champions.push(champion_id);
+            }
+        }
+    }
+    Ok(locked_champions)
+}
+
+/// Updates the locked champions of the loaded games of the series played after `series_game`,
+/// once the picks of `series_game` changed.
+async fn update_series_locked_champions(app_state: &AppState, series_id: i32, series_game: i32) {
+    if let Err(e) = push_series_locked_champions(app_state, series_id, series_game).await {
+        error!("failed to update the locked champions of series {series_id}: {e}");
+    }
+}
+
+async fn push_series_locked_champions(
+    app_state: &AppState,
+    series_id: i32,
+    series_game: i32,
+) -> Result<(), sqlx::Error> {
+    let games = database::query_series_games(&app_state.pool, series_id).await?;
+    for game in games {
+        if game.series_game <= series_game || !app_state.drafts.contains_key(&game.client_id) {
+            continue;
+        }
+        let locked_champions =
+            series_locked_champions(app_state, series_id, Some(game.series_game)).await?;
+        if let Some(mut server_draft) = app_state.drafts.get_mut(&game.client_id) {
+            if server_draft.draft.locked_champions != locked_champions {
+                server_draft.draft.locked_champions = locked_champions.clone();
+                ws::broadcast(
+                    &server_draft.events_sender,
+                    WsEvent::LockedChampionsChanged(locked_champions),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn get_series_id(app_state: &AppState, client_id: Uuid) -> Result<i32, sqlx::Error> {
+    match database::query_series_id(&app_state.pool, client_id).await? {
+        Some(series_id) => Ok(series_id),
+        None => database::new_series(&app_state.pool, client_id).await,
+    }
+}
+
+async fn query_series(app_state: &AppState, series_id: i32) -> Result<Series, sqlx::Error> {
+    let games = database::query_series_games(&app_state.pool, series_id)
+        .await?
+        .into_iter()
+        .map(|game| game.client_id)
+        .collect();
+    let locked_champions = series_locked_champions(app_state, series_id, None).await?;
+    Ok(Series {
+        games,
+        locked_champions,
+    })
+}
+
+async fn get_series(
+    extract::Path(client_id): extract::Path<Uuid>,
+    State(app_state): State<AppState>,
+) -> Result<Json<Series>, ApiError> {
+    let series_id = database::query_series_id(&app_state.pool, client_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    Ok(Json(query_series(&app_state, series_id).await?))
+}
+
+/// Adds a new game to the series, creating the series if it does not exist yet. The draft of the
+/// game is loaded when a client first opens it.
+async fn new_series_game(
+    extract::Path(client_id): extract::Path<Uuid>,
+    State(app_state): State<AppState>,
+) -> Result<Json<Series>, ApiError> {
+    let series_id = get_series_id(&app_state, client_id).await?;
+    database::new_series_draft(&app_state.pool, Uuid::new_v4(), series_id).await?;
+    Ok(Json(query_series(&app_state, series_id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use draft_together_data::ChampionRole;
+
+    use super::*;
+
+    fn server_draft() -> ServerDraft {
+        let links = DraftLinks {
+            spectator_id: Uuid::nil(),
+            blue_token: Uuid::nil(),
+            red_token: Uuid::nil(),
+        };
+        ServerDraft::new(0, links, Draft::default())
+    }
+
+    #[test]
+    fn reset_without_slots_still_bumps_the_revision() {
+        let mut server_draft = server_draft();
+        let mut roles = [None; 5];
+        roles[0] = Some(ChampionRole::TOP);
+        server_draft.draft.set_roles(Team::Blue, roles);
+        server_draft.draft.locked_in.push(Team::Red);
+        server_draft.trades.push(TradeProposal {
+            id: Uuid::nil(),
+            from: ChampionPosition::Blue1,
+            to: ChampionPosition::Blue2,
+            proposed_by: Uuid::nil(),
+        });
+
+        let (update, trades) = server_draft.reset();
+
+        assert!(update.changes.is_empty());
+        assert_eq!(update.revision, 1);
+        assert_eq!(server_draft.draft.revision, 1);
+        assert!(server_draft.dirty);
+        assert_eq!(server_draft.draft.roles(Team::Blue), &[None; 5]);
+        assert!(server_draft.draft.locked_in.is_empty());
+        assert_eq!(trades.len(), 1);
+        assert!(server_draft.trades.is_empty());
+        assert_eq!(
+            server_draft.updates_since(0).map(|updates| updates.len()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn events_are_numbered_in_recording_order() {
+        let mut server_draft = server_draft();
+        server_draft.last_sequence = 4;
+        server_draft.record(None, DraftOperation::Reset);
+        server_draft.record(
+            Some(Uuid::nil()),
+            DraftOperation::LockIn { team: Team::Blue },
+        );
+
+        let sequences: Vec<u64> = server_draft
+            .pending_events
+            .iter()
+            .map(|event| event.sequence)
+            .collect();
+        assert_eq!(sequences, [5, 6]);
+        assert!(server_draft.dirty);
+
+        server_draft.events_saved(Some(5));
+        assert_eq!(server_draft.pending_events.len(), 1);
+        assert_eq!(server_draft.pending_events[0].sequence, 6);
+    }
+
+    #[test]
+    fn links_give_restricted_access() {
+        let links = DraftLinks {
+            spectator_id: Uuid::new_v4(),
+            blue_token: Uuid::new_v4(),
+            red_token: Uuid::new_v4(),
+        };
+
+        assert_eq!(link_access(&links, links.spectator_id), Access::Spectator);
+        assert_eq!(
+            link_access(&links, links.blue_token),
+            Access::Captain(Team::Blue)
+        );
+        assert_eq!(
+            link_access(&links, links.red_token),
+            Access::Captain(Team::Red)
+        );
     }
 }
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use draft_together_data::{ChampionId, ChampionRole};
+
+/// Assigns a different role to as many picks as possible, each pick only getting one of the
+/// roles its champion is played in. Picks that can not get a role are left unassigned.
+pub fn assign_roles(
+    picks: &[Option<ChampionId>; 5],
+    champion_roles: &HashMap<ChampionId, Vec<ChampionRole>>,
+) -> [Option<ChampionRole>; 5] {
+    let candidates: Vec<&[ChampionRole]> = picks
+        .iter()
+        .map(|pick| {
+            pick.and_then(|champion_id| champion_roles.get(&champion_id))
+                .map_or(&[][..], |roles| roles.as_slice())
+        })
+        .collect();
+
+    // pick playing each role of `ChampionRole::ALL`
+    let mut role_picks: [Option<usize>; 5] = [None; 5];
+    for pick in 0..picks.len() {
+        let mut visited = [false; 5];
+        find_role(pick, &candidates, &mut role_picks, &mut visited);
+    }
+
+    let mut roles = [None; 5];
+    for (role, pick) in ChampionRole::ALL.into_iter().zip(role_picks) {
+        if let Some(pick) = pick {
+            roles[pick] = Some(role);
+        }
+    }
+    roles
+}
+
+/// Gives `pick` one of its candidate roles, moving the picks already playing them to another of
+/// their roles when needed. Returns false if no role could be found.
+fn find_role(
+    pick: usize,
+    candidates: &[&[ChampionRole]],
+    role_picks: &mut [Option<usize>; 5],
+    visited: &mut [bool; 5],
+) -> bool {
+    for role in candidates[pick] {
+        let role = role_index(*role);
+        if visited[role] {
+            continue;
+        }
+        visited[role] = true;
+        let available = match role_picks[role] {
+            None => true,
+            Some(other_pick) => find_role(other_pick, candidates, role_picks, visited),
+        };
+        if available {
+            role_picks[role] = Some(pick);
+            return true;
+        }
+    }
+    false
+}
+
+fn role_index(role: ChampionRole) -> usize {
+    ChampionRole::ALL
+        .iter()
+        .position(|&other| other == role)
+        .expect("every role is in ChampionRole::ALL")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ChampionRole::*;
+
+    fn champion_roles(
+        roles: &[(ChampionId, &[ChampionRole])],
+    ) -> HashMap<ChampionId, Vec<ChampionRole>> {
+        roles
+            .iter()
+            .map(|(champion_id, roles)| (*champion_id, roles.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn single_role_champions_get_their_role() {
+        let champion_roles = champion_roles(&[
+            (1, &[TOP]),
+            (2, &[JUNGLE]),
+            (3, &[MID]),
+            (4, &[BOT]),
+            (5, &[SUPPORT]),
+        ]);
+        assert_eq!(
+            assign_roles(
+                &[Some(5), Some(3), Some(1), Some(4), Some(2)],
+                &champion_roles
+            ),
+            [Some(SUPPORT), Some(MID), Some(TOP), Some(BOT), Some(JUNGLE)]
+        );
+    }
+
+    #[test]
+    fn assigned_picks_move_to_free_a_role() {
+        // the first pick takes top, then has to move to mid for the second pick to play top
+        let champion_roles = champion_roles(&[(1, &[TOP, MID]), (2, &[TOP]), (3, &[MID, BOT])]);
+        assert_eq!(
+            assign_roles(&[Some(1), Some(2), Some(3), None, None], &champion_roles),
+            [Some(MID), Some(TOP), Some(BOT), None, None]
+        );
+    }
+
+    #[test]
+    fn picks_without_a_free_role_stay_unassigned() {
+        let champion_roles = champion_roles(&[(1, &[SUPPORT]), (2, &[SUPPORT]), (3, &[])]);
+        assert_eq!(
+            assign_roles(&[Some(1), Some(2), Some(3), Some(4), None], &champion_roles),
+            [Some(SUPPORT), None, None, None, None]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use draft_together_data::{
+    ChampionId, ChampionPosition, ChampionUpdate, DraftChanges, DraftLifecycle, DraftOperation,
+    Team, TimeoutFallback, TimerSettings, TimerState, Turn,
+};
+use rand::seq::IteratorRandom;
+use tracing::{debug, trace};
+use uuid::Uuid;
+
+use crate::{
+    update_series_locked_champions,
+    ws::{broadcast, lifecycle_changed, WsEvent},
+    AppState, ServerDraft,
+};
+
+const TICK: Duration = Duration::from_secs(1);
+
+/// Timer of the current turn of a tournament draft, owned by the server.
+#[derive(Debug)]
+pub struct TurnTimer {
+    step: usize,
+    turn_started_at: Instant,
+    blue_reserve: Duration,
+    red_reserve: Duration,
+}
+
+impl TurnTimer {
+    fn new(step: usize, settings: &TimerSettings, now: Instant) -> Self {
+        let reserve = Duration::from_secs(settings.reserve_seconds);
+        Self {
+            step,
+            turn_started_at: now,
+            blue_reserve: reserve,
+            red_reserve: reserve,
+        }
+    }
+
+    fn reserve_mut(&mut self, team: Team) -> &mut Duration {
+        match team {
+            Team::Blue => &mut self.blue_reserve,
+            Team::Red => &mut self.red_reserve,
+        }
+    }
+}
+
+enum TimerStatus {
+    Running(TimerState),
+    Expired(Turn, TimeoutFallback),
+}
+
+pub async fn run_turn_timers(app_state: AppState) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+
+        let draft_ids: Vec<Uuid> = app_state.drafts.iter().map(|draft| *draft.key()).collect();
+        for draft_id in draft_ids {
+            tick_draft(&app_state, draft_id).await;
+        }
+    }
+}
+
+async fn tick_draft(app_state: &AppState, draft_id: Uuid) {
+    let status = match app_state.drafts.get_mut(&draft_id) {
+        Some(mut server_draft) => update_timer(&mut server_draft, Instant::now()),
+        None => return,
+    };
+
+    match status {
+        None => {}
+        Some(TimerStatus::Running(timer)) => {
+            if let Some(server_draft) = app_state.drafts.get(&draft_id) {
+                broadcast(&server_draft.events_sender, WsEvent::TimerChanged(timer));
+            }
+        }
+        Some(TimerStatus::Expired(turn, fallback)) => {
+            let hovers: Vec<(Option<Team>, ChampionUpdate)> = app_state
+                .drafts_sessions
+                .get(&draft_id)
+                .map(|sessions| {
+                    sessions
+                        .iter()
+                        .filter_map(|session| Some((session.team, session.hover.clone()?)))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let valid_champion_ids = app_state.valid_champion_ids.read().await.clone();
+
+            let picked_series = if let Some(mut server_draft) = app_state.drafts.get_mut(&draft_id)
+            {
+                let lifecycle = server_draft.lifecycle;
+                let update = apply_timeout(
+                    &mut server_draft,
+                    &turn,
+                    fallback,
+                    &hovers,
+                    &valid_champion_ids,
+                );
+                debug!(
+                    "turn {} of draft {draft_id} ran out of time, {fallback:?} applied",
+                    turn.step
+                );
+                update_timer(&mut server_draft, Instant::now());
+                let step = server_draft.draft.step;
+                let picked = !update.changes.is_empty();
+                let operation = if picked {
+                    DraftOperation::Update {
+                        changes: update.changes.clone(),
+                        step,
+                    }
+                } else {
+                    DraftOperation::SkipTurn { step }
+                };
+                // the timeout is applied by the server, not by a session
+                server_draft.record(None, operation);
+                let events_sender = &server_draft.events_sender;
+                if picked {
+                    broadcast(events_sender, WsEvent::DraftUpdate(update));
+                }
+                broadcast(
+                    events_sender,
+                    WsEvent::TurnChanged(server_draft.draft.next_turn()),
+                );
+                if server_draft.lifecycle != lifecycle {
+                    broadcast(events_sender, lifecycle_changed(&server_draft));
+                }
+                server_draft.series.filter(|_| picked)
+            } else {
+                None
+            };
+            if let Some((series_id, series_game)) = picked_series {
+                update_series_locked_champions(app_state, series_id, series_game).await;
+            }
+        }
+    }
+}
+
+/// Starts, moves to the next turn or stops the timer of the draft depending on its current turn.
+/// Returns `None` if the current turn is not timed.
+fn update_timer(server_draft: &mut ServerDraft, now: Instant) -> Option<TimerStatus> {
+    let started = matches!(
+        server_draft.lifecycle,
+        DraftLifecycle::Ready | DraftLifecycle::InProgress
+    );
+    let (true, Some(settings), Some(turn)) = (
+        started,
+        server_draft.draft.settings.timer.clone(),
+        server_draft.draft.next_turn(),
+    ) else {
+        server_draft.timer = None;
+        return None;
+    };
+    let turn_duration = Duration::from_secs(settings.turn_seconds);
+
+    let timer = server_draft
+        .timer
+        .get_or_insert_with(|| TurnTimer::new(turn.step, &settings, now));
+    if turn.step < timer.step {
+        // the draft was reset
+        *timer = TurnTimer::new(turn.step, &settings, now);
+    } else if turn.step > timer.step {
+        let previous_team = server_draft
+            .draft
+            .settings
+            .format
+            .turn(timer.step)
+            .map_or(turn.team, |previous_turn| previous_turn.team);
+        let overtime = now
+            .duration_since(timer.turn_started_at)
+            .saturating_sub(turn_duration);
+        let reserve = timer.reserve_mut(previous_team);
+        *reserve = reserve.saturating_sub(overtime);
+        timer.step = turn.step;
+        timer.turn_started_at = now;
+    }
+
+    let elapsed = now.duration_since(timer.turn_started_at);
+    let overtime = elapsed.saturating_sub(turn_duration);
+    let reserve = *timer.reserve_mut(turn.team);
+    if elapsed >= turn_duration && overtime >= reserve {
+        *timer.reserve_mut(turn.team) = Duration::ZERO;
+        return Some(TimerStatus::Expired(turn, settings.fallback));
+    }
+
+    let reserve_seconds = |team: Team, reserve: Duration| {
+        if team == turn.team {
+            reserve.saturating_sub(overtime).as_secs()
+        } else {
+            reserve.as_secs()
+        }
+    };
+    Some(TimerStatus::Running(TimerState {
+        team: turn.team,
+        remaining_seconds: turn_duration.saturating_sub(elapsed).as_secs(),
+        blue_reserve_seconds: reserve_seconds(Team::Blue, timer.blue_reserve),
+        red_reserve_seconds: reserve_seconds(Team::Red, timer.red_reserve),
+    }))
+}
+
+/// Keeps the timer in sync after the draft turn was changed by a client.
+pub fn sync_timer(server_draft: &mut ServerDraft) {
+    update_timer(server_draft, Instant::now());
+}
+
+fn apply_timeout(
+    server_draft: &mut ServerDraft,
+    turn: &Turn,
+    fallback: TimeoutFallback,
+    hovers: &[(Option<Team>, ChampionUpdate)],
+    valid_champion_ids: &HashSet<ChampionId>,
+) -> DraftChanges {
+    let champion_id = match fallback {
+        TimeoutFallback::Skip => None,
+        TimeoutFallback::LockHover => hovers
+            .iter()
+            // only the team whose turn it is chooses its champion
+            .filter(|(team, hover)| *team == Some(turn.team) && hover.position == turn.position)
+            .find_map(|(_, hover)| hover.champion_id),
+        TimeoutFallback::Random => {
+            let draft = &server_draft.draft;
+            valid_champion_ids
+                .iter()
+                .filter(|&&champion_id| !draft.locked_champions.contains(&champion_id))
+                .filter(|&&champion_id| {
+                    draft.settings.allow_duplicate_champions
+                        || !ChampionPosition::ALL
+                            .iter()
+                            .any(|&position| draft.get(position) == Some(champion_id))
+                })
+                .choose(&mut rand::thread_rng())
+                .copied()
+        }
+    };
+
+    let update = champion_id.and_then(|champion_id| {
+        server_draft
+            .edit(|draft| {
+                draft.try_update(&ChampionUpdate {
+                    champion_id: Some(champion_id),
+                    position: turn.position,
+                })
+            })
+            .inspect_err(|e| trace!("timeout fallback could not lock {champion_id}: {e}"))
+            .ok()
+    });
+
+    update.unwrap_or_else(|| {
+        server_draft.draft.skip_turn();
+        server_draft.dirty = true;
+        server_draft.update_lifecycle();
+        DraftChanges {
+            changes: Vec::new(),
+            revision: server_draft.draft.revision,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use draft_together_data::{Draft, DraftLinks, DraftMode, DraftSettings};
+
+    use super::*;
+
+    fn timed_draft(lifecycle: DraftLifecycle) -> ServerDraft {
+        let draft = Draft {
+            settings: DraftSettings {
+                mode: DraftMode::Tournament,
+                timer: Some(TimerSettings {
+                    turn_seconds: 30,
+                    reserve_seconds: 60,
+                    fallback: TimeoutFallback::Skip,
+                }),
+                ..DraftSettings::default()
+            },
+            ..Draft::default()
+        };
+        let links = DraftLinks {
+            spectator_id: Uuid::nil(),
+            blue_token: Uuid::nil(),
+            red_token: Uuid::nil(),
+        };
+        let mut server_draft = ServerDraft::new(0, links, draft);
+        server_draft.lifecycle = lifecycle;
+        server_draft
+    }
+
+    fn running(status: Option<TimerStatus>) -> TimerState {
+        match status {
+            Some(TimerStatus::Running(state)) => state,
+            Some(TimerStatus::Expired(turn, _)) => panic!("turn {} expired", turn.step),
+            None => panic!("turn is not timed"),
+        }
+    }
+
+    #[test]
+    fn timer_waits_for_the_draft_to_start() {
+        let mut server_draft = timed_draft(DraftLifecycle::Lobby);
+        assert!(update_timer(&mut server_draft, Instant::now()).is_none());
+        assert!(server_draft.timer.is_none());
+    }
+
+    #[test]
+    fn turn_time_is_spent_before_the_reserve() {
+        let mut server_draft = timed_draft(DraftLifecycle::InProgress);
+        let start = Instant::now();
+
+        let state = running(update_timer(&mut server_draft, start));
+        assert_eq!(state.team, Team::Blue);
+        assert_eq!(state.remaining_seconds, 30);
+        assert_eq!(state.blue_reserve_seconds, 60);
+
+        let state = running(update_timer(
+            &mut server_draft,
+            start + Duration::from_secs(10),
+        ));
+        assert_eq!(state.remaining_seconds, 20);
+        assert_eq!(state.blue_reserve_seconds, 60);
+
+        let state = running(update_timer(
+            &mut server_draft,
+            start + Duration::from_secs(45),
+        ));
+        assert_eq!(state.remaining_seconds, 0);
+        assert_eq!(state.blue_reserve_seconds, 45);
+        assert_eq!(state.red_reserve_seconds, 60);
+    }
+
+    #[test]
+    fn overtime_is_taken_from_the_reserve_of_the_previous_turn() {
+        let mut server_draft = timed_draft(DraftLifecycle::InProgress);
+        let start = Instant::now();
+        update_timer(&mut server_draft, start);
+
+        server_draft.draft.skip_turn();
+        let state = running(update_timer(
+            &mut server_draft,
+            start + Duration::from_secs(50),
+        ));
+        assert_eq!(state.team, Team::Red);
+        assert_eq!(state.remaining_seconds, 30);
+        assert_eq!(state.blue_reserve_seconds, 40);
+        assert_eq!(state.red_reserve_seconds, 60);
+    }
+
+    #[test]
+    fn turn_expires_once_the_reserve_is_spent() {
+        let mut server_draft = timed_draft(DraftLifecycle::InProgress);
+        let start = Instant::now();
+        update_timer(&mut server_draft, start);
+
+        match update_timer(&mut server_draft, start + Duration::from_secs(90)) {
+            Some(TimerStatus::Expired(turn, fallback)) => {
+                assert_eq!(turn.step, 0);
+                assert_eq!(fallback, TimeoutFallback::Skip);
+            }
+            _ => panic!("turn should have expired"),
+        }
+        let timer = server_draft
+            .timer
+            .as_ref()
+            .expect("timer should be running");
+        assert_eq!(timer.blue_reserve, Duration::ZERO);
+        assert_eq!(timer.red_reserve, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn reset_restarts_the_timer() {
+        let mut server_draft = timed_draft(DraftLifecycle::InProgress);
+        let start = Instant::now();
+        update_timer(&mut server_draft, start);
+        server_draft.draft.skip_turn();
+        update_timer(&mut server_draft, start + Duration::from_secs(50));
+
+        server_draft.draft.reset();
+        let state = running(update_timer(
+            &mut server_draft,
+            start + Duration::from_secs(60),
+        ));
+        assert_eq!(state.team, Team::Blue);
+        assert_eq!(state.remaining_seconds, 30);
+        assert_eq!(state.blue_reserve_seconds, 60);
+    }
+}
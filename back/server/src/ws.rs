@@ -2,30 +2,208 @@ use anyhow::Result;
 use axum::{
     extract::{
         self,
-        ws::{Message, WebSocket},
+        ws::{close_code, CloseFrame, Message, WebSocket},
         ConnectInfo, State, WebSocketUpgrade,
     },
     response::IntoResponse,
 };
 use axum_extra::TypedHeader;
-use draft_together_data::ChampionUpdate;
+use dashmap::Entry;
+use draft_together_data::{
+    ChampionId, ChampionPosition, ChampionRole, ChampionUpdate, ClientMessage, Draft, DraftAction,
+    DraftChanges, DraftError, DraftLifecycle, DraftMode, DraftOperation, DraftSettings, Revision,
+    ServerMessage, Session, SessionId, Team, TimerState, TradeProposal, Turn,
+};
 use futures::{stream::SplitSink, SinkExt, StreamExt};
-use std::net::SocketAddr;
-use tokio::sync::broadcast::Sender;
+use serde::Deserialize;
+use std::{collections::HashMap, net::SocketAddr};
+use tokio::sync::{
+    broadcast::{error::SendError, Sender},
+    mpsc,
+};
 use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::{database, get_current_draft, get_current_draft_mut, AppState};
+use crate::{
+    database, get_current_draft, get_current_draft_mut, resolve_draft_id, roles, timer,
+    update_series_locked_champions, AppState, ServerDraft,
+};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum WsEvent {
-    DraftUpdate,
+    DraftUpdate(DraftChanges),
+    PresenceChanged(Vec<Session>),
+    SettingsChanged(DraftSettings),
+    TurnChanged(Option<Turn>),
+    TimerChanged(TimerState),
+    Hovered {
+        session_id: SessionId,
+        hover: Option<ChampionUpdate>,
+    },
+    LockedIn(Team),
+    Revealed,
+    TradeProposed(TradeProposal),
+    LifecycleChanged {
+        lifecycle: DraftLifecycle,
+        ready_teams: Vec<Team>,
+    },
+    TradeResolved {
+        trade_id: Uuid,
+        accepted: bool,
+    },
+    RolesChanged {
+        team: Team,
+        roles: [Option<ChampionRole>; 5],
+        missing_roles: Vec<ChampionRole>,
+    },
+    /// Champions picked in the previous games of the series changed.
+    LockedChampionsChanged(Vec<ChampionId>),
+    /// The server is shutting down, the sockets are closed.
+    Shutdown,
+}
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+
+/// What a socket is allowed to do, depending on the id it joined the draft with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Joined with the client id of the draft.
+    Editor,
+    /// Joined with the spectator id of the draft, only receives its updates.
+    Spectator,
+    /// Joined with a captain token instead of the client id, only edits the positions of its
+    /// team.
+    Captain(Team),
+}
+
+impl Access {
+    /// Errors if the client can not edit the positions of `team`.
+    fn ensure_can_edit_team(self, team: Team) -> Result<(), ClientMessageError> {
+        match self {
+            Access::Editor => Ok(()),
+            Access::Captain(captain_team) if captain_team == team => Ok(()),
+            Access::Captain(_) => Err(ClientMessageError::OtherTeam),
+            Access::Spectator => Err(ClientMessageError::ReadOnly),
+        }
+    }
+
+    /// Errors if the client can not edit `position`.
+    fn ensure_can_edit(self, position: ChampionPosition) -> Result<(), ClientMessageError> {
+        self.ensure_can_edit_team(position.team())
+    }
+
+    /// Errors if the client can not edit both teams.
+    fn ensure_full_access(self) -> Result<(), ClientMessageError> {
+        match self {
+            Access::Editor => Ok(()),
+            Access::Captain(_) => Err(ClientMessageError::CaptainRestricted),
+            Access::Spectator => Err(ClientMessageError::ReadOnly),
+        }
+    }
+
+    /// The team shown in the presence of a client that asked for `team`: captains are always in
+    /// their team and spectators in none.
+    fn session_team(self, team: Option<Team>) -> Option<Team> {
+        match self {
+            Access::Editor => team,
+            Access::Captain(captain_team) => Some(captain_team),
+            Access::Spectator => None,
+        }
+    }
+
+    /// The team whose hidden picks of a blind draft the client can see and edit. Only set by a
+    /// captain token, the team chosen by a session does not give access to them.
+    fn side(self) -> Option<Team> {
+        match self {
+            Access::Captain(team) => Some(team),
+            Access::Editor | Access::Spectator => None,
+        }
+    }
+}
+
+/// How draft updates are pushed to a socket: the whole draft or only the changed slots.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateMode {
+    #[default]
+    Full,
+    Diff,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WsParams {
+    #[serde(default)]
+    updates: UpdateMode,
+    /// Set by reconnecting clients to receive the updates they missed instead of a snapshot.
+    last_revision: Option<Revision>,
+    name: Option<String>,
+    team: Option<Team>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum ClientMessageError {
+    #[error("invalid message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("invalid champion id: {0}")]
+    InvalidChampionId(ChampionId),
+    #[error("draft is at revision {revision}, edit expected revision {expected_revision}")]
+    Conflict {
+        expected_revision: Revision,
+        revision: Revision,
+    },
+    #[error(transparent)]
+    Draft(#[from] DraftError),
+    #[error("nothing to undo")]
+    NothingToUndo,
+    #[error("nothing to redo")]
+    NothingToRedo,
+    #[error("a team has to be chosen first")]
+    NoTeam,
+    #[error("only a team captain can do this")]
+    NotCaptain,
+    #[error("no pending trade with id {0}")]
+    UnknownTrade(Uuid),
+    #[error("a trade has to be accepted by another session of its team")]
+    NotTradePartner,
+    #[error("spectators can not edit the draft")]
+    ReadOnly,
+    #[error("captains can only edit the positions of their team")]
+    OtherTeam,
+    #[error("captains can not edit both teams at once")]
+    CaptainRestricted,
+    #[error("error while fetching database: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<ClientMessageError> for ServerMessage {
+    fn from(value: ClientMessageError) -> Self {
+        match value {
+            ClientMessageError::Conflict {
+                expected_revision,
+                revision,
+            } => Self::Conflict {
+                expected_revision,
+                revision,
+            },
+            e => Self::Error {
+                reason: e.to_string(),
+            },
+        }
+    }
+}
+
+impl ClientMessageError {
+    /// Errors caused by the server itself end the socket, the others are reported to the client.
+    fn is_fatal(&self) -> bool {
+        matches!(self, Self::Database(_))
+    }
 }
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     extract::Path(draft_client_id): extract::Path<Uuid>,
+    extract::Query(params): extract::Query<WsParams>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(app_state): State<AppState>,
 ) -> impl IntoResponse {
@@ -36,15 +214,86 @@ pub async fn ws_handler(
     };
     println!("`{user_agent}` at {addr} connected.");
 
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, draft_client_id, app_state))
+    ws.on_upgrade(move |socket| handle_socket(socket, addr, draft_client_id, params, app_state))
 }
 
-async fn handle_socket(socket: WebSocket, who: SocketAddr, draft_id: Uuid, app_state: AppState) {
-    update_draft_connected_clients(&app_state, draft_id);
+async fn handle_socket(
+    socket: WebSocket,
+    who: SocketAddr,
+    joined_id: Uuid,
+    params: WsParams,
+    app_state: AppState,
+) {
+    let (draft_id, access) = match resolve_draft_id(&app_state, joined_id).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            error!("failed to find draft {joined_id} for {who}: {e}");
+            return;
+        }
+    };
+    let team = access.session_team(params.team);
+    let session = Session {
+        id: Uuid::new_v4(),
+        name: display_name(params.name),
+        team,
+        hover: None,
+        spectator: access == Access::Spectator,
+    };
+    let session_id = session.id;
+    register_session(&app_state, draft_id, session.clone());
+
+    let updates = params.updates;
+    // subscribe while the draft is borrowed so that no update can happen before the first message
+    let (draft_tx, mut draft_rx, first_message, turn, lifecycle, links, trades) =
+        match get_current_draft(&app_state, draft_id).await {
+            Ok(server_draft) => (
+                server_draft.events_sender.clone(),
+                server_draft.events_sender.subscribe(),
+                match params.last_revision {
+                    Some(last_revision) => {
+                        resume_message(&server_draft, last_revision, access.side())
+                    }
+                    None => ServerMessage::DraftState {
+                        draft: server_draft.draft.view_for(access.side()),
+                    },
+                },
+                server_draft.draft.next_turn(),
+                ServerMessage::LifecycleChanged {
+                    lifecycle: server_draft.lifecycle,
+                    ready_teams: server_draft.ready_teams.clone(),
+                },
+                Some(server_draft.links.clone()).filter(|_| access == Access::Editor),
+                server_draft.trades.clone(),
+            ),
+            Err(e) => {
+                error!("failed to load draft {draft_id} for {who}: {e}");
+                if let Err(e) =
+                    update_database_if_last_client(&app_state, draft_id, session_id, &who).await
+                {
+                    error!("error while updating database: {e}");
+                }
+                return;
+            }
+        };
 
     let (mut sender, mut receiver) = socket.split();
-    let draft_tx = app_state.events_sender.clone();
-    let mut draft_rx = draft_tx.subscribe();
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<ServerMessage>();
+    for message in [
+        ServerMessage::Welcome {
+            session,
+            links,
+            trades,
+        },
+        first_message,
+        ServerMessage::TurnChanged { turn },
+        lifecycle,
+    ] {
+        reply_tx
+            .send(message)
+            .expect("reply receiver should not be dropped before the socket tasks start");
+    }
+    broadcast_presence(&app_state, &draft_tx, draft_id);
+    let presence_tx = draft_tx.clone();
 
     let app_state_receiver = app_state.clone();
     let mut client_receiver_task = tokio::spawn(async move {
@@ -54,17 +303,29 @@ async fn handle_socket(socket: WebSocket, who: SocketAddr, draft_id: Uuid, app_s
                 Message::Close(close_frame) => {
                     debug!("{who} closed connection: {close_frame:?}");
                 }
-                Message::Text(champion_update) => {
-                    if let Err(e) = receive_draft_update(
+                Message::Text(client_message) => {
+                    let reply = match receive_client_message(
                         &app_state_receiver,
                         &draft_tx,
-                        &champion_update,
+                        &client_message,
                         draft_id,
+                        session_id,
+                        access,
                         &who,
                     )
                     .await
                     {
-                        error!("stopping web socket from {who} because of an error while receiving draft update from client: {e}");
+                        Ok(reply) => reply,
+                        Err(e) if e.is_fatal() => {
+                            error!("stopping web socket from {who} because of an error while receiving message from client: {e}");
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("message from {who} was rejected: {e}");
+                            e.into()
+                        }
+                    };
+                    if reply_tx.send(reply).is_err() {
                         break;
                     }
                 }
@@ -75,10 +336,28 @@ async fn handle_socket(socket: WebSocket, who: SocketAddr, draft_id: Uuid, app_s
 
     let app_state_sender = app_state.clone();
     let mut send_draft_update_tasks = tokio::spawn(async move {
-        while let Ok(event) = draft_rx.recv().await {
-            if let Err(e) = send_draft_update(event, &mut sender, &app_state_sender, draft_id).await
-            {
-                error!("stopping web socket because of an error while sending draft update to client: {e}");
+        loop {
+            let result = tokio::select! {
+                event = draft_rx.recv() => match event {
+                    Ok(WsEvent::Shutdown) => {
+                        let close_frame = CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server is shutting down".into(),
+                        };
+                        if let Err(e) = sender.send(Message::Close(Some(close_frame))).await {
+                            warn!("failed to send close frame to {who}: {e}");
+                        }
+                        break;
+                    }
+                    Ok(event) => send_draft_update(event, &mut sender, &app_state_sender, draft_id, session_id, access, updates).await,
+                    Err(_) => break,
+                },
+                Some(reply) = reply_rx.recv() => send_message(&mut sender, &reply).await,
+            };
+            if let Err(e) = result {
+                error!(
+                    "stopping web socket because of an error while sending message to client: {e}"
+                );
                 break;
             }
         }
@@ -93,101 +372,942 @@ async fn handle_socket(socket: WebSocket, who: SocketAddr, draft_id: Uuid, app_s
         }
     }
 
+    withdraw_trades(&app_state, &presence_tx, draft_id, session_id);
     // check if it was the last client
-    if let Err(e) = update_database_if_last_client(&app_state, draft_id, &who).await {
+    if let Err(e) = update_database_if_last_client(&app_state, draft_id, session_id, &who).await {
         error!("error while updating database: {e}");
     }
+    broadcast(
+        &presence_tx,
+        WsEvent::Hovered {
+            session_id,
+            hover: None,
+        },
+    );
+    broadcast_presence(&app_state, &presence_tx, draft_id);
 
     info!("Websocket context {who} destroyed");
 }
 
+async fn send_message(
+    sender: &mut SplitSink<WebSocket, Message>,
+    message: &ServerMessage,
+) -> Result<()> {
+    sender
+        .send(Message::Text(
+            serde_json::to_string(message).expect("serialization of message should not fail"),
+        ))
+        .await?;
+    Ok(())
+}
+
 async fn send_draft_update(
     event: WsEvent,
     sender: &mut SplitSink<WebSocket, Message>,
     app_state: &AppState,
     draft_id: Uuid,
+    session_id: SessionId,
+    access: Access,
+    updates: UpdateMode,
 ) -> Result<()> {
-    if let WsEvent::DraftUpdate = event {
-        let server_draft = get_current_draft(app_state, draft_id).await?;
-        sender
-            .send(Message::Text(
-                serde_json::to_string(&server_draft.draft)
-                    .expect("serialization of draft should not fail"),
-            ))
+    let side = access.side();
+    match event {
+        WsEvent::DraftUpdate(update) => {
+            let message = {
+                let draft = &get_current_draft(app_state, draft_id).await?.draft;
+                match updates {
+                    UpdateMode::Full => ServerMessage::DraftState {
+                        draft: draft.view_for(side),
+                    },
+                    UpdateMode::Diff => {
+                        ServerMessage::DraftDiff(draft.visible_changes(&update, side))
+                    }
+                }
+            };
+            send_message(sender, &message).await?;
+        }
+        WsEvent::LockedChampionsChanged(locked_champions) => {
+            send_message(
+                sender,
+                &ServerMessage::LockedChampionsChanged { locked_champions },
+            )
+            .await?;
+        }
+        WsEvent::LockedIn(team) => {
+            send_message(sender, &ServerMessage::LockedIn { team }).await?;
+        }
+        WsEvent::RolesChanged {
+            team: roles_team,
+            roles,
+            missing_roles,
+        } => {
+            let hidden = {
+                let draft = &get_current_draft(app_state, draft_id).await?.draft;
+                ChampionPosition::new(roles_team, DraftAction::Pick, 0)
+                    .is_some_and(|position| draft.is_hidden_from(position, side))
+            };
+            if !hidden {
+                send_message(
+                    sender,
+                    &ServerMessage::RolesChanged {
+                        team: roles_team,
+                        roles,
+                        missing_roles,
+                    },
+                )
+                .await?;
+            }
+        }
+        WsEvent::LifecycleChanged {
+            lifecycle,
+            ready_teams,
+        } => {
+            send_message(
+                sender,
+                &ServerMessage::LifecycleChanged {
+                    lifecycle,
+                    ready_teams,
+                },
+            )
             .await?;
+        }
+        WsEvent::TradeProposed(trade) => {
+            send_message(sender, &ServerMessage::TradeProposed { trade }).await?;
+        }
+        WsEvent::TradeResolved { trade_id, accepted } => {
+            send_message(sender, &ServerMessage::TradeResolved { trade_id, accepted }).await?;
+        }
+        WsEvent::Revealed => {
+            let draft = get_current_draft(app_state, draft_id).await?.draft.clone();
+            send_message(sender, &ServerMessage::Revealed { draft }).await?;
+        }
+        WsEvent::SettingsChanged(settings) => {
+            send_message(sender, &ServerMessage::SettingsChanged { settings }).await?;
+        }
+        WsEvent::TimerChanged(timer) => {
+            send_message(sender, &ServerMessage::TimerChanged { timer }).await?;
+        }
+        WsEvent::TurnChanged(turn) => {
+            send_message(sender, &ServerMessage::TurnChanged { turn }).await?;
+        }
+        WsEvent::PresenceChanged(mut sessions) => {
+            {
+                let draft = &get_current_draft(app_state, draft_id).await?.draft;
+                for session in &mut sessions {
+                    session.hover = visible_hover(draft, session.hover.take(), side);
+                }
+            }
+            send_message(sender, &ServerMessage::PresenceChanged { sessions }).await?;
+        }
+        WsEvent::Hovered {
+            session_id: hovering_session_id,
+            hover,
+        } => {
+            if hovering_session_id != session_id {
+                let hover = visible_hover(
+                    &get_current_draft(app_state, draft_id).await?.draft,
+                    hover,
+                    side,
+                );
+                send_message(
+                    sender,
+                    &ServerMessage::Hovered {
+                        session_id: hovering_session_id,
+                        hover,
+                    },
+                )
+                .await?;
+            }
+        }
+        WsEvent::Shutdown => {}
     }
     Ok(())
 }
 
-async fn receive_draft_update(
+/// `hover`, or `None` if it is on a pick hidden from the clients of `team`.
+fn visible_hover(
+    draft: &Draft,
+    hover: Option<ChampionUpdate>,
+    team: Option<Team>,
+) -> Option<ChampionUpdate> {
+    hover.filter(|hover| !draft.is_hidden_from(hover.position, team))
+}
+
+async fn receive_client_message(
     app_state: &AppState,
     draft_tx: &Sender<WsEvent>,
-    champion_update: &str,
+    client_message: &str,
     draft_id: Uuid,
+    session_id: SessionId,
+    access: Access,
     who: &SocketAddr,
-) -> Result<()> {
-    let champion_update: ChampionUpdate = serde_json::from_str(champion_update)?;
-
-    if let Some(champion_id) = champion_update.champion_id {
-        if !app_state
-            .valid_champion_ids
-            .read()
-            .await
-            .contains(&champion_id)
+) -> Result<ServerMessage, ClientMessageError> {
+    let client_message: ClientMessage = serde_json::from_str(client_message)?;
+    if access == Access::Spectator && !client_message.is_read_only() {
+        return Err(ClientMessageError::ReadOnly);
+    }
+
+    match client_message {
+        ClientMessage::Update {
+            update,
+            expected_revision,
+        } => {
+            access.ensure_can_edit(update.position)?;
+            validate_champion_id(app_state, update.champion_id).await?;
+
+            let side = access.side();
+            let revision = edit_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                expected_revision,
+                |draft| {
+                    draft.ensure_editable_by(update.position, side)?;
+                    draft.try_update(&update)
+                },
+            )
+            .await?;
+            debug!("{who} updated draft {draft_id} with {update:?} (revision {revision})");
+        }
+        ClientMessage::Swap {
+            from,
+            to,
+            expected_revision,
+        } => {
+            access.ensure_can_edit(from)?;
+            access.ensure_can_edit(to)?;
+            let revision = edit_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                expected_revision,
+                |draft| {
+                    draft.ensure_free_mode()?;
+                    draft.swap(from, to);
+                    // the champions may not be allowed at their new position
+                    draft.validate_position(from)?;
+                    draft.validate_position(to)
+                },
+            )
+            .await?;
+            debug!("{who} swapped {from:?} and {to:?} in draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::Move {
+            from,
+            to,
+            expected_revision,
+        } => {
+            access.ensure_can_edit(from)?;
+            access.ensure_can_edit(to)?;
+            let revision = edit_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                expected_revision,
+                |draft| {
+                    draft.ensure_free_mode()?;
+                    draft.move_champion(from, to);
+                    // the champions may not be allowed at their new position
+                    draft.validate_position(from)?;
+                    draft.validate_position(to)
+                },
+            )
+            .await?;
+            debug!("{who} moved {from:?} to {to:?} in draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::Reset { expected_revision } => {
+            access.ensure_full_access()?;
+            let (series, update, turn, roles, trades, lifecycle) = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                server_draft.ensure_editable()?;
+                ensure_revision(&server_draft, expected_revision)?;
+                let lifecycle = server_draft.lifecycle;
+                let (update, trades) = server_draft.reset();
+                server_draft.record(Some(session_id), DraftOperation::Reset);
+                timer::sync_timer(&mut server_draft);
+                let draft = &server_draft.draft;
+                let roles = [Team::Blue, Team::Red].map(|team| WsEvent::RolesChanged {
+                    team,
+                    roles: *draft.roles(team),
+                    missing_roles: draft.missing_roles(team),
+                });
+                (
+                    server_draft.series,
+                    update,
+                    draft.next_turn(),
+                    roles,
+                    trades,
+                    (server_draft.lifecycle != lifecycle).then(|| lifecycle_changed(&server_draft)),
+                )
+            };
+            let revision = update.revision;
+            let picked = !update.changes.is_empty();
+            broadcast(draft_tx, WsEvent::DraftUpdate(update));
+            for event in roles {
+                broadcast(draft_tx, event);
+            }
+            broadcast(draft_tx, WsEvent::TurnChanged(turn));
+            for trade in trades {
+                broadcast(
+                    draft_tx,
+                    WsEvent::TradeResolved {
+                        trade_id: trade.id,
+                        accepted: false,
+                    },
+                );
+            }
+            if let Some(lifecycle) = lifecycle {
+                broadcast(draft_tx, lifecycle);
+            }
+            if let Some((series_id, series_game)) = series.filter(|_| picked) {
+                update_series_locked_champions(app_state, series_id, series_game).await;
+            }
+            debug!("{who} reset draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::UpdateSettings { settings } => {
+            access.ensure_full_access()?;
+            let turn = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                if server_draft.lifecycle == DraftLifecycle::Locked {
+                    return Err(DraftError::DraftLocked.into());
+                }
+                server_draft.draft.set_settings(settings.clone())?;
+                let operation = DraftOperation::Settings {
+                    settings: settings.clone(),
+                    step: server_draft.draft.step,
+                };
+                server_draft.record(Some(session_id), operation);
+                timer::sync_timer(&mut server_draft);
+                server_draft.draft.next_turn()
+            };
+            broadcast(draft_tx, WsEvent::SettingsChanged(settings));
+            broadcast(draft_tx, WsEvent::TurnChanged(turn));
+            debug!("{who} updated settings of draft {draft_id}");
+        }
+        ClientMessage::Undo { expected_revision } => {
+            access.ensure_full_access()?;
+            let revision = change_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                expected_revision,
+                |server_draft| {
+                    server_draft.draft.ensure_free_mode()?;
+                    Ok(server_draft
+                        .undo()
+                        .ok_or(ClientMessageError::NothingToUndo)??)
+                },
+            )
+            .await?;
+            debug!("{who} undid last edit of draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::Redo { expected_revision } => {
+            access.ensure_full_access()?;
+            let revision = change_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                expected_revision,
+                |server_draft| {
+                    server_draft.draft.ensure_free_mode()?;
+                    Ok(server_draft
+                        .redo()
+                        .ok_or(ClientMessageError::NothingToRedo)??)
+                },
+            )
+            .await?;
+            debug!("{who} redid last undone edit of draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::Snapshot => {
+            let draft = get_current_draft(app_state, draft_id)
+                .await?
+                .draft
+                .view_for(access.side());
+            return Ok(ServerMessage::DraftState { draft });
+        }
+        ClientMessage::Resume { last_revision } => {
+            let server_draft = get_current_draft(app_state, draft_id).await?;
+            return Ok(resume_message(&server_draft, last_revision, access.side()));
+        }
+        ClientMessage::LockIn => {
+            let team = access.side().ok_or(ClientMessageError::NotCaptain)?;
+            let revealed = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                server_draft.ensure_editable()?;
+                server_draft.draft.lock_in(team)?;
+                server_draft.record(Some(session_id), DraftOperation::LockIn { team });
+                server_draft.draft.revealed()
+            };
+            broadcast(draft_tx, WsEvent::LockedIn(team));
+            if revealed {
+                broadcast(draft_tx, WsEvent::Revealed);
+                debug!("picks of draft {draft_id} were revealed");
+            }
+            debug!("{who} locked in {team:?} picks of draft {draft_id}");
+        }
+        ClientMessage::Identify { name, team } => {
+            update_session(app_state, draft_id, session_id, |session| {
+                session.name = display_name(name);
+                session.team = access.session_team(team);
+            });
+            broadcast_presence(app_state, draft_tx, draft_id);
+        }
+        ClientMessage::Hover {
+            position,
+            champion_id,
+        } => {
+            access.ensure_can_edit(position)?;
+            validate_champion_id(app_state, champion_id).await?;
+
+            let hover = champion_id.map(|champion_id| ChampionUpdate {
+                champion_id: Some(champion_id),
+                position,
+            });
+            update_session(app_state, draft_id, session_id, |session| {
+                session.hover = hover.clone()
+            });
+            broadcast(draft_tx, WsEvent::Hovered { session_id, hover });
+            trace!("{who} hovered {champion_id:?} on {position:?} in draft {draft_id}");
+        }
+        ClientMessage::AssignRole { position, role } => {
+            access.ensure_can_edit(position)?;
+            let side = access.side();
+            change_roles(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                position.team(),
+                |draft| {
+                    draft.ensure_editable_by(position, side)?;
+                    draft.set_role(position, role)
+                },
+            )
+            .await?;
+            debug!("{who} assigned {role:?} to {position:?} in draft {draft_id}");
+        }
+        ClientMessage::AutoAssignRoles { team: roles_team } => {
+            access.ensure_can_edit_team(roles_team)?;
+            let champion_roles: HashMap<ChampionId, Vec<ChampionRole>> =
+                database::query_champions(&app_state.pool)
+                    .await?
+                    .into_iter()
+                    .map(|champion| (champion.id, champion.positions.0))
+                    .collect();
+            let side = access.side();
+            let missing_roles = change_roles(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                roles_team,
+                |draft| {
+                    if let Some(position) = ChampionPosition::new(roles_team, DraftAction::Pick, 0)
+                    {
+                        draft.ensure_editable_by(position, side)?;
+                    }
+                    let picks = match roles_team {
+                        Team::Blue => &draft.blue_champions,
+                        Team::Red => &draft.red_champions,
+                    };
+                    let roles = roles::assign_roles(picks, &champion_roles);
+                    draft.set_roles(roles_team, roles);
+                    Ok(())
+                },
+            )
+            .await?;
+            debug!("{who} auto assigned {roles_team:?} roles in draft {draft_id}, missing roles: {missing_roles:?}");
+        }
+        ClientMessage::ProposeTrade { from, to } => {
+            let team =
+                session_team(app_state, draft_id, session_id).ok_or(ClientMessageError::NoTeam)?;
+            let trade = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                server_draft.ensure_editable()?;
+                server_draft.draft.ensure_tradable(from, to)?;
+                if from.team() != team {
+                    return Err(DraftError::NotYourSide { position: from }.into());
+                }
+                let trade = TradeProposal {
+                    id: Uuid::new_v4(),
+                    from,
+                    to,
+                    proposed_by: session_id,
+                };
+                server_draft.trades.push(trade.clone());
+                trade
+            };
+            debug!(
+                "{who} proposed trade {} of {from:?} and {to:?} in draft {draft_id}",
+                trade.id
+            );
+            broadcast(draft_tx, WsEvent::TradeProposed(trade));
+        }
+        ClientMessage::AcceptTrade { trade_id } => {
+            let team = session_team(app_state, draft_id, session_id);
+            let revision = change_draft(
+                app_state,
+                draft_tx,
+                draft_id,
+                session_id,
+                None,
+                |server_draft| {
+                    let trade = server_draft
+                        .trades
+                        .iter()
+                        .find(|trade| trade.id == trade_id)
+                        .cloned()
+                        .ok_or(ClientMessageError::UnknownTrade(trade_id))?;
+                    if trade.proposed_by == session_id || team != Some(trade.from.team()) {
+                        return Err(ClientMessageError::NotTradePartner);
+                    }
+                    server_draft.draft.ensure_tradable(trade.from, trade.to)?;
+                    let update = server_draft.edit(|draft| {
+                        draft.swap(trade.from, trade.to);
+                        Ok(())
+                    })?;
+                    server_draft.trades.retain(|trade| trade.id != trade_id);
+                    Ok(update)
+                },
+            )
+            .await?;
+            broadcast(
+                draft_tx,
+                WsEvent::TradeResolved {
+                    trade_id,
+                    accepted: true,
+                },
+            );
+            debug!("{who} accepted trade {trade_id} in draft {draft_id} (revision {revision})");
+        }
+        ClientMessage::DeclineTrade { trade_id } => {
+            let team = session_team(app_state, draft_id, session_id);
+            {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                let trade = server_draft
+                    .trades
+                    .iter()
+                    .find(|trade| trade.id == trade_id)
+                    .ok_or(ClientMessageError::UnknownTrade(trade_id))?;
+                if trade.proposed_by != session_id && team != Some(trade.from.team()) {
+                    return Err(ClientMessageError::NotTradePartner);
+                }
+                server_draft.trades.retain(|trade| trade.id != trade_id);
+            }
+            broadcast(
+                draft_tx,
+                WsEvent::TradeResolved {
+                    trade_id,
+                    accepted: false,
+                },
+            );
+            debug!("{who} declined trade {trade_id} in draft {draft_id}");
+        }
+        ClientMessage::Ready { ready } => {
+            let team =
+                session_team(app_state, draft_id, session_id).ok_or(ClientMessageError::NoTeam)?;
+            let event = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                server_draft.set_ready(team, ready)?;
+                timer::sync_timer(&mut server_draft);
+                lifecycle_changed(&server_draft)
+            };
+            broadcast(draft_tx, event);
+            debug!("{who} marked {team:?} as ready: {ready} in draft {draft_id}");
+        }
+        ClientMessage::Lock => {
+            // captains and spectators joined with the links issued by the draft creator
+            access.ensure_full_access()?;
+            let (update, event) = {
+                let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+                server_draft.lock()?;
+                timer::sync_timer(&mut server_draft);
+                let update: database::DraftDatabaseUpdate = (&*server_draft).into();
+                (update, lifecycle_changed(&server_draft))
+            };
+            // saved right away, the draft result is final
+            database::update_drafts(&app_state.pool, std::slice::from_ref(&update)).await?;
+            if let Some(mut server_draft) = app_state.drafts.get_mut(&draft_id) {
+                server_draft.events_saved(update.last_sequence());
+            }
+            broadcast(draft_tx, event);
+            debug!("{who} locked draft {draft_id}");
+        }
+        ClientMessage::Ping => trace!("ping received from {who}"),
+    }
+
+    Ok(ServerMessage::Ack)
+}
+
+async fn validate_champion_id(
+    app_state: &AppState,
+    champion_id: Option<ChampionId>,
+) -> Result<(), ClientMessageError> {
+    match champion_id {
+        Some(champion_id)
+            if !app_state
+                .valid_champion_ids
+                .read()
+                .await
+                .contains(&champion_id) =>
         {
-            error!("champion update was not valid: champion_id {champion_id} was not valid");
-            return Ok(());
+            Err(ClientMessageError::InvalidChampionId(champion_id))
         }
+        _ => Ok(()),
     }
+}
 
-    {
+fn resume_message(
+    server_draft: &ServerDraft,
+    last_revision: Revision,
+    team: Option<Team>,
+) -> ServerMessage {
+    let draft = &server_draft.draft;
+    match server_draft.updates_since(last_revision) {
+        Some(updates) => ServerMessage::Replay {
+            updates: updates
+                .iter()
+                .map(|update| draft.visible_changes(update, team))
+                .collect(),
+        },
+        None => ServerMessage::DraftState {
+            draft: draft.view_for(team),
+        },
+    }
+}
+
+/// Applies `edit` to the draft and records it in the draft history, see `change_draft`.
+async fn edit_draft(
+    app_state: &AppState,
+    draft_tx: &Sender<WsEvent>,
+    draft_id: Uuid,
+    session_id: SessionId,
+    expected_revision: Option<Revision>,
+    edit: impl FnOnce(&mut Draft) -> Result<(), DraftError>,
+) -> Result<Revision, ClientMessageError> {
+    change_draft(
+        app_state,
+        draft_tx,
+        draft_id,
+        session_id,
+        expected_revision,
+        |server_draft| Ok(server_draft.edit(edit)?),
+    )
+    .await
+}
+
+/// Runs `change` under a single borrow of the draft and notifies the draft sockets of the
+/// changed slots, which are recorded in the draft event log as made by `session_id`. The change
+/// is rejected if the draft is no longer at `expected_revision`.
+async fn change_draft(
+    app_state: &AppState,
+    draft_tx: &Sender<WsEvent>,
+    draft_id: Uuid,
+    session_id: SessionId,
+    expected_revision: Option<Revision>,
+    change: impl FnOnce(&mut ServerDraft) -> Result<DraftChanges, ClientMessageError>,
+) -> Result<Revision, ClientMessageError> {
+    let (series, update, tournament, turn, lifecycle) = {
         let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
-        server_draft.draft.update(&champion_update);
+        server_draft.ensure_editable()?;
+        ensure_revision(&server_draft, expected_revision)?;
+        let lifecycle = server_draft.lifecycle;
+        let update = change(&mut server_draft)?;
+        if !update.changes.is_empty() {
+            let operation = DraftOperation::Update {
+                changes: update.changes.clone(),
+                step: server_draft.draft.step,
+            };
+            server_draft.record(Some(session_id), operation);
+        }
+        timer::sync_timer(&mut server_draft);
+        let draft = &server_draft.draft;
+        (
+            server_draft.series,
+            update,
+            draft.settings.mode == DraftMode::Tournament,
+            draft.next_turn(),
+            (server_draft.lifecycle != lifecycle).then(|| lifecycle_changed(&server_draft)),
+        )
+    };
+    let revision = update.revision;
+    if !update.changes.is_empty() {
+        broadcast(draft_tx, WsEvent::DraftUpdate(update));
+        if tournament {
+            broadcast(draft_tx, WsEvent::TurnChanged(turn));
+        }
+        if let Some((series_id, series_game)) = series {
+            update_series_locked_champions(app_state, series_id, series_game).await;
+        }
+    }
+    if let Some(lifecycle) = lifecycle {
+        broadcast(draft_tx, lifecycle);
     }
-    draft_tx.send(WsEvent::DraftUpdate)?;
-    debug!("{who} updated draft {draft_id} with {champion_update:?}");
 
-    Ok(())
+    Ok(revision)
 }
 
-fn update_draft_connected_clients(app_state: &AppState, draft_id: Uuid) {
-    if app_state.drafts_connected_clients.contains_key(&draft_id) {
-        app_state
-            .drafts_connected_clients
-            .alter(&draft_id, |_, value| value.saturating_add(1))
+/// Rejects a change made against `expected_revision` if the draft is no longer at it.
+fn ensure_revision(
+    server_draft: &ServerDraft,
+    expected_revision: Option<Revision>,
+) -> Result<(), ClientMessageError> {
+    match expected_revision {
+        Some(expected_revision) if expected_revision != server_draft.draft.revision => {
+            Err(ClientMessageError::Conflict {
+                expected_revision,
+                revision: server_draft.draft.revision,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Applies `change` to the roles of the draft, records it as made by `session_id` and notifies
+/// the draft sockets of the roles of `team`. Returns the roles no pick of `team` plays.
+async fn change_roles(
+    app_state: &AppState,
+    draft_tx: &Sender<WsEvent>,
+    draft_id: Uuid,
+    session_id: SessionId,
+    team: Team,
+    change: impl FnOnce(&mut Draft) -> Result<(), DraftError>,
+) -> Result<Vec<ChampionRole>, ClientMessageError> {
+    let (roles, missing_roles) = {
+        let mut server_draft = get_current_draft_mut(app_state, draft_id).await?;
+        server_draft.ensure_editable()?;
+        change(&mut server_draft.draft)?;
+        let roles = *server_draft.draft.roles(team);
+        server_draft.record(Some(session_id), DraftOperation::Roles { team, roles });
+        (roles, server_draft.draft.missing_roles(team))
+    };
+    broadcast(
+        draft_tx,
+        WsEvent::RolesChanged {
+            team,
+            roles,
+            missing_roles: missing_roles.clone(),
+        },
+    );
+    Ok(missing_roles)
+}
+
+pub fn lifecycle_changed(server_draft: &ServerDraft) -> WsEvent {
+    WsEvent::LifecycleChanged {
+        lifecycle: server_draft.lifecycle,
+        ready_teams: server_draft.ready_teams.clone(),
+    }
+}
+
+pub fn broadcast(draft_tx: &Sender<WsEvent>, event: WsEvent) {
+    if let Err(SendError(event)) = draft_tx.send(event) {
+        trace!("no socket subscribed to receive {event:?}");
+    }
+}
+
+/// Trims the display name chosen by a client, an empty name is no name.
+fn display_name(name: Option<String>) -> Option<String> {
+    name.map(|name| {
+        name.trim()
+            .chars()
+            .take(MAX_DISPLAY_NAME_LENGTH)
+            .collect::<String>()
+    })
+    .filter(|name| !name.is_empty())
+}
+
+fn broadcast_presence(app_state: &AppState, draft_tx: &Sender<WsEvent>, draft_id: Uuid) {
+    let sessions = app_state
+        .drafts_sessions
+        .get(&draft_id)
+        .map(|sessions| sessions.clone())
+        .unwrap_or_default();
+    broadcast(draft_tx, WsEvent::PresenceChanged(sessions));
+}
+
+fn session_team(app_state: &AppState, draft_id: Uuid, session_id: SessionId) -> Option<Team> {
+    app_state
+        .drafts_sessions
+        .get(&draft_id)?
+        .iter()
+        .find(|session| session.id == session_id)?
+        .team
+}
+
+fn update_session(
+    app_state: &AppState,
+    draft_id: Uuid,
+    session_id: SessionId,
+    update: impl FnOnce(&mut Session),
+) {
+    if let Some(mut sessions) = app_state.drafts_sessions.get_mut(&draft_id) {
+        if let Some(session) = sessions.iter_mut().find(|session| session.id == session_id) {
+            update(session);
+        }
+    }
+}
+
+/// Removes a draft without clients from memory, unless it changed since it was saved, e.g. by
+/// a turn timeout, in which case it stays loaded so that the next flush saves it.
+fn remove_saved_draft(app_state: &AppState, draft_id: Uuid) {
+    if app_state
+        .drafts
+        .remove_if(&draft_id, |_, server_draft| !server_draft.dirty)
+        .is_some()
+    {
+        debug!(
+            "no clients connected for draft with id {draft_id}, draft was removed from hashmaps"
+        );
     } else {
-        app_state.drafts_connected_clients.insert(draft_id, 1);
+        debug!("draft with id {draft_id} changed while it was saved, draft is kept");
     }
 }
 
+/// Withdraws the pending trades proposed by `session_id`, which left the draft.
+fn withdraw_trades(
+    app_state: &AppState,
+    draft_tx: &Sender<WsEvent>,
+    draft_id: Uuid,
+    session_id: SessionId,
+) {
+    let withdrawn: Vec<TradeProposal> = match app_state.drafts.get_mut(&draft_id) {
+        Some(mut server_draft) => {
+            let (withdrawn, kept) = std::mem::take(&mut server_draft.trades)
+                .into_iter()
+                .partition(|trade| trade.proposed_by == session_id);
+            server_draft.trades = kept;
+            withdrawn
+        }
+        None => Vec::new(),
+    };
+    for trade in withdrawn {
+        broadcast(
+            draft_tx,
+            WsEvent::TradeResolved {
+                trade_id: trade.id,
+                accepted: false,
+            },
+        );
+    }
+}
+
+fn register_session(app_state: &AppState, draft_id: Uuid, session: Session) {
+    app_state
+        .drafts_sessions
+        .entry(draft_id)
+        .or_default()
+        .push(session);
+}
+
 async fn update_database_if_last_client(
     app_state: &AppState,
     draft_id: Uuid,
+    session_id: SessionId,
     who: &SocketAddr,
 ) -> Result<()> {
-    app_state
-        .drafts_connected_clients
-        .alter(&draft_id, |_, value| value.saturating_sub(1));
-    let connected_clients = app_state.drafts_connected_clients.get(&draft_id);
+    let connected_clients = app_state
+        .drafts_sessions
+        .get_mut(&draft_id)
+        .map(|mut sessions| {
+            sessions.retain(|session| session.id != session_id);
+            sessions.len()
+        });
     if connected_clients.is_none() {
-        error!("at the end of the socket with {who}, the number of connected client is None. Trying to save in database anyway...");
+        error!("at the end of the socket with {who}, the draft has no session registry. Trying to save in database anyway...");
     }
-    if connected_clients.is_none() || connected_clients.is_some_and(|value| *value == 0) {
-        let server_draft = app_state.drafts.get(&draft_id);
-        if let Some(server_draft) = server_draft {
-            database::update_draft(&app_state.pool, &server_draft).await?;
-            // drop to not deadlock on remove
-            drop(server_draft);
-
-            info!("draft with id {draft_id} was successfully updated in database");
-            app_state.drafts_connected_clients.remove(&draft_id);
-            app_state.drafts.remove(&draft_id);
-            trace!("draft with id {draft_id} was sucessfully removed from drafts");
-
-            debug!("no clients connected for draft with id {draft_id}, draft was removed from hashmaps");
+    if connected_clients.is_none() || connected_clients.is_some_and(|value| value == 0) {
+        // copied out so that the draft is not borrowed while it is written
+        let update = app_state.drafts.get_mut(&draft_id).map(|mut server_draft| {
+            std::mem::take(&mut server_draft.dirty)
+                .then(|| database::DraftDatabaseUpdate::from(&*server_draft))
+        });
+        if let Some(update) = update {
+            if let Some(update) = update {
+                if let Err(e) =
+                    database::update_drafts(&app_state.pool, std::slice::from_ref(&update)).await
+                {
+                    // kept in memory, the next flush saves it
+                    if let Some(mut server_draft) = app_state.drafts.get_mut(&draft_id) {
+                        server_draft.dirty = true;
+                    }
+                    return Err(e.into());
+                }
+                if let Some(mut server_draft) = app_state.drafts.get_mut(&draft_id) {
+                    server_draft.events_saved(update.last_sequence());
+                }
+                info!("draft with id {draft_id} was successfully updated in database");
+            } else {
+                debug!("draft with id {draft_id} has no unsaved changes");
+            }
+            // the sessions entry stays locked while the draft is removed, a client joining
+            // meanwhile waits and then loads the draft again
+            match app_state.drafts_sessions.entry(draft_id) {
+                Entry::Occupied(sessions) if !sessions.get().is_empty() => {
+                    debug!("a client joined draft with id {draft_id} while it was saved, draft is kept");
+                }
+                Entry::Occupied(sessions) => {
+                    sessions.remove();
+                    remove_saved_draft(app_state, draft_id);
+                }
+                Entry::Vacant(_) => remove_saved_draft(app_state, draft_id),
+            }
         } else {
             warn!("failed to save draft with id {draft_id} to database: draft was never loaded");
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captains_only_edit_their_team() {
+        for (team, other) in [(Team::Blue, Team::Red), (Team::Red, Team::Blue)] {
+            let access = Access::Captain(team);
+            let own = ChampionPosition::new(team, DraftAction::Pick, 0).unwrap();
+            let others = ChampionPosition::new(other, DraftAction::Ban, 0).unwrap();
+
+            assert!(access.ensure_can_edit(own).is_ok());
+            assert!(matches!(
+                access.ensure_can_edit(others),
+                Err(ClientMessageError::OtherTeam)
+            ));
+            assert!(matches!(
+                access.ensure_full_access(),
+                Err(ClientMessageError::CaptainRestricted)
+            ));
+            assert_eq!(access.session_team(None), Some(team));
+            assert_eq!(access.session_team(Some(other)), Some(team));
+            assert_eq!(access.side(), Some(team));
+        }
+    }
+
+    #[test]
+    fn editors_edit_both_teams_and_spectators_none() {
+        for team in [Team::Blue, Team::Red] {
+            let position = ChampionPosition::new(team, DraftAction::Pick, 0).unwrap();
+            assert!(Access::Editor.ensure_can_edit(position).is_ok());
+            assert!(matches!(
+                Access::Spectator.ensure_can_edit(position),
+                Err(ClientMessageError::ReadOnly)
+            ));
+            assert_eq!(Access::Editor.session_team(Some(team)), Some(team));
+            assert_eq!(Access::Spectator.session_team(Some(team)), None);
+        }
+        // the team chosen by an editor does not reveal the hidden picks of that team
+        assert_eq!(Access::Editor.side(), None);
+        assert_eq!(Access::Spectator.side(), None);
+        assert!(Access::Editor.ensure_full_access().is_ok());
+        assert!(matches!(
+            Access::Spectator.ensure_full_access(),
+            Err(ClientMessageError::ReadOnly)
+        ));
+    }
+}
@@ -11,18 +11,210 @@ export interface Draft {
   red_champions: ChampionIdsList;
   blue_bans: ChampionIdsList;
   red_bans: ChampionIdsList;
+  revision: number;
+  settings: DraftSettings;
+  step: number;
+  locked_champions: number[];
+  locked_in: Team[];
+  blue_roles: RolesList;
+  red_roles: RolesList;
+}
+
+export type ChampionRole = "TOP" | "JUNGLE" | "MID" | "BOT" | "SUPPORT";
+
+export type RolesList = [
+  ChampionRole | null,
+  ChampionRole | null,
+  ChampionRole | null,
+  ChampionRole | null,
+  ChampionRole | null,
+];
+
+export interface DraftLinks {
+  spectator_id: string;
+  blue_token: string;
+  red_token: string;
+}
+
+export interface TradeProposal {
+  id: string;
+  from: string;
+  to: string;
+  proposed_by: string;
+}
+
+export interface Series {
+  games: string[];
+  locked_champions: number[];
+}
+
+export enum DraftLifecycle {
+  Lobby = "Lobby",
+  Ready = "Ready",
+  InProgress = "InProgress",
+  Completed = "Completed",
+  Locked = "Locked",
+}
+
+export enum DraftMode {
+  Free = "Free",
+  Tournament = "Tournament",
+  Blind = "Blind",
+}
+
+export interface DraftSettings {
+  allow_duplicate_champions: boolean;
+  mode: DraftMode;
+  timer: TimerSettings | null;
+  format: DraftFormat;
+}
+
+export interface DraftPhase {
+  action: "Ban" | "Pick";
+  teams: Team[];
+}
+
+export interface DraftFormat {
+  picks_per_side: number;
+  bans_per_side: number;
+  phases: DraftPhase[];
+}
+
+export interface TimerSettings {
+  turn_seconds: number;
+  reserve_seconds: number;
+  fallback: "Skip" | "LockHover" | "Random";
+}
+
+export interface TimerState {
+  team: Team;
+  remaining_seconds: number;
+  blue_reserve_seconds: number;
+  red_reserve_seconds: number;
+}
+
+export interface Turn {
+  step: number;
+  team: Team;
+  action: "Ban" | "Pick";
+  position: string;
 }
 
 export interface DraftUpdate {
-  champion_id: number;
+  champion_id: number | null;
   position: string;
 }
 
+export type ClientMessage =
+  | ({ type: "update"; expected_revision?: number } & DraftUpdate)
+  | { type: "swap"; from: string; to: string; expected_revision?: number }
+  | { type: "move"; from: string; to: string; expected_revision?: number }
+  | { type: "reset"; expected_revision?: number }
+  | { type: "update_settings"; settings: DraftSettings }
+  | { type: "undo"; expected_revision?: number }
+  | { type: "redo"; expected_revision?: number }
+  | { type: "snapshot" }
+  | { type: "resume"; last_revision: number }
+  | { type: "identify"; name: string | null; team: Team | null }
+  | { type: "hover"; position: string; champion_id: number | null }
+  | { type: "lock_in" }
+  | { type: "assign_role"; position: string; role: ChampionRole | null }
+  | { type: "auto_assign_roles"; team: Team }
+  | { type: "propose_trade"; from: string; to: string }
+  | { type: "accept_trade"; trade_id: string }
+  | { type: "decline_trade"; trade_id: string }
+  | { type: "ready"; ready: boolean }
+  | { type: "lock" }
+  | { type: "ping" };
+
+export type ServerMessage =
+  | {
+      type: "welcome";
+      session: Session;
+      links: DraftLinks | null;
+      trades: TradeProposal[];
+    }
+  | { type: "draft_state"; draft: Draft }
+  | ({ type: "draft_diff" } & DraftChanges)
+  | { type: "replay"; updates: DraftChanges[] }
+  | { type: "error"; reason: string }
+  | { type: "conflict"; expected_revision: number; revision: number }
+  | { type: "settings_changed"; settings: DraftSettings }
+  | { type: "turn_changed"; turn: Turn | null }
+  | { type: "timer_changed"; timer: TimerState }
+  | { type: "hovered"; session_id: string; hover: DraftUpdate | null }
+  | { type: "presence_changed"; sessions: Session[] }
+  | { type: "locked_in"; team: Team }
+  | { type: "revealed"; draft: Draft }
+  | { type: "trade_proposed"; trade: TradeProposal }
+  | {
+      type: "lifecycle_changed";
+      lifecycle: DraftLifecycle;
+      ready_teams: Team[];
+    }
+  | { type: "trade_resolved"; trade_id: string; accepted: boolean }
+  | {
+      type: "roles_changed";
+      team: Team;
+      roles: RolesList;
+      missing_roles: ChampionRole[];
+    }
+  | { type: "locked_champions_changed"; locked_champions: number[] }
+  | { type: "ack" };
+
+export interface DraftChanges {
+  changes: DraftUpdate[];
+  revision: number;
+}
+
+export type DraftOperation =
+  | { type: "update"; changes: DraftUpdate[]; step: number }
+  | { type: "skip_turn"; step: number }
+  | { type: "roles"; team: Team; roles: RolesList }
+  | { type: "settings"; settings: DraftSettings; step: number }
+  | { type: "lock_in"; team: Team }
+  | { type: "reset" };
+
+export interface DraftEvent {
+  /** Position of the event in the log of its draft, from 1. */
+  sequence: number;
+  revision: number;
+  session_id: string | null;
+  /** Milliseconds since the Unix epoch. */
+  timestamp: number;
+  operation: DraftOperation;
+}
+
 export enum Team {
   Blue = "Blue",
   Red = "Red",
 }
 
+export const STANDARD_FORMAT: DraftFormat = {
+  picks_per_side: 5,
+  bans_per_side: 5,
+  phases: [
+    {
+      action: "Ban",
+      teams: [Team.Blue, Team.Red, Team.Blue, Team.Red, Team.Blue, Team.Red],
+    },
+    {
+      action: "Pick",
+      teams: [Team.Blue, Team.Red, Team.Red, Team.Blue, Team.Blue, Team.Red],
+    },
+    { action: "Ban", teams: [Team.Red, Team.Blue, Team.Red, Team.Blue] },
+    { action: "Pick", teams: [Team.Red, Team.Blue, Team.Blue, Team.Red] },
+  ],
+};
+
+export interface Session {
+  id: string;
+  name: string | null;
+  team: Team | null;
+  hover: DraftUpdate | null;
+  spectator: boolean;
+}
+
 export interface Selection {
   team: Team;
   isBan: boolean;
@@ -44,3 +236,23 @@ export function computePosition(
   const index_server = index + 1;
   return isBan ? `${team}Ban${index_server}` : `${team}${index_server}`;
 }
+
+export function applyDraftChanges(draft: Draft, update: DraftChanges) {
+  for (const change of update.changes) {
+    const match = change.position.match(/^(Blue|Red)(Ban)?(\d)$/);
+    if (match === null) {
+      continue;
+    }
+    const [, team, ban, index] = match;
+    const slots =
+      team === Team.Blue
+        ? ban
+          ? draft.blue_bans
+          : draft.blue_champions
+        : ban
+          ? draft.red_bans
+          : draft.red_champions;
+    slots[Number(index) - 1] = change.champion_id;
+  }
+  draft.revision = update.revision;
+}